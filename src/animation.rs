@@ -0,0 +1,136 @@
+use std::error::Error;
+use std::fs::File;
+use std::io::{BufWriter, Write};
+use std::path::{Path, PathBuf};
+
+use crate::render::{Frame, Renderer};
+
+enum Encoder {
+    Gif(gif::Encoder<BufWriter<File>>),
+    /// Uncompressed YUV 4:4:4 frames; players and ffmpeg read it as is.
+    Y4m(BufWriter<File>),
+}
+
+/// Animation file; the format follows the extension: `.gif` or `.y4m`.
+pub struct Animation {
+    path: PathBuf,
+    fps: u16,
+    /// Opened with the first frame, which fixes the size of the picture.
+    encoder: Option<Encoder>,
+    size: (usize, usize),
+}
+
+impl Animation {
+    pub fn new(path: &Path, fps: u16) -> Result<Self, Box<dyn Error>> {
+        match path.extension().and_then(|e| e.to_str()) {
+            Some("gif") | Some("y4m") => Ok(Self { path: path.to_path_buf(), fps: fps.max(1), encoder: None, size: (0, 0) }),
+            _ => Err(format!("unknown animation format {:?} (expected .gif or .y4m)", path).into()),
+        }
+    }
+
+    fn open(&self, frame: &Frame) -> Result<Encoder, Box<dyn Error>> {
+        let out = BufWriter::new(File::create(&self.path)?);
+        if self.path.extension().is_some_and(|e| e == "gif") {
+            let (w, h) = (u16::try_from(frame.width)?, u16::try_from(frame.height)?);
+            let mut encoder = gif::Encoder::new(out, w, h, &[])?;
+            encoder.set_repeat(gif::Repeat::Infinite)?;
+            Ok(Encoder::Gif(encoder))
+        } else {
+            let mut out = out;
+            writeln!(out, "YUV4MPEG2 W{} H{} F{}:1 Ip A1:1 C444", frame.width, frame.height, self.fps)?;
+            Ok(Encoder::Y4m(out))
+        }
+    }
+
+    /// Append `frame`; every frame has to be the size of the first one.
+    pub fn push(&mut self, frame: &Frame) -> Result<(), Box<dyn Error>> {
+        if self.encoder.is_none() {
+            self.encoder = Some(self.open(frame)?);
+            self.size = (frame.width, frame.height);
+        }
+        if (frame.width, frame.height) != self.size {
+            return Err(format!("frame is {}x{}, the animation {}x{}",
+                               frame.width, frame.height, self.size.0, self.size.1).into());
+        }
+        match self.encoder.as_mut().expect("opened above") {
+            Encoder::Gif(encoder) => {
+                // the size fit in u16 when the encoder was opened
+                let mut f = gif::Frame::from_rgb_speed(frame.width as u16, frame.height as u16, &frame.rgb, 10);
+                // in hundredths of a second; faster than 100 fps is shown at 100
+                f.delay = (100 / self.fps).max(1);
+                encoder.write_frame(&f)?;
+            },
+            Encoder::Y4m(out) => {
+                writeln!(out, "FRAME")?;
+                // BT.601, studio range
+                let planes: [fn(f32, f32, f32) -> f32; 3] = [
+                    |r, g, b| 16.0 + 0.257 * r + 0.504 * g + 0.098 * b,
+                    |r, g, b| 128.0 - 0.148 * r - 0.291 * g + 0.439 * b,
+                    |r, g, b| 128.0 + 0.439 * r - 0.368 * g - 0.071 * b,
+                ];
+                for plane in planes {
+                    let bytes: Vec<u8> = frame.rgb.chunks(3)
+                        .map(|p| plane(p[0] as f32, p[1] as f32, p[2] as f32).round() as u8)
+                        .collect();
+                    out.write_all(&bytes)?;
+                }
+            },
+        }
+        Ok(())
+    }
+
+    pub fn finish(self) -> Result<(), Box<dyn Error>> {
+        match self.encoder {
+            // writes the trailer
+            Some(Encoder::Gif(encoder)) => { encoder.into_inner()?.flush()?; },
+            Some(Encoder::Y4m(mut out)) => out.flush()?,
+            None => {},
+        }
+        Ok(())
+    }
+}
+
+/// Everything drawn during a run: PNG frames and an animation, each every so many steps.
+pub struct Recorder {
+    pub renderer: Renderer,
+    pub png_dir: Option<PathBuf>,
+    pub png_every: usize,
+    pub animation: Option<Animation>,
+    pub animation_every: usize,
+    /// Write the step and the number of cells on the frames.
+    pub caption: bool,
+}
+
+impl Recorder {
+    fn png_due(&self, step: usize) -> bool {
+        self.png_dir.is_some() && step.is_multiple_of(self.png_every.max(1))
+    }
+
+    fn animation_due(&self, step: usize) -> bool {
+        self.animation.is_some() && step.is_multiple_of(self.animation_every.max(1))
+    }
+
+    /// Draw the frame of `step` with `draw` if any output wants it.
+    pub fn record(&mut self, step: usize, cells: usize,
+                  draw: impl FnOnce(&Renderer) -> Frame) -> Result<(), Box<dyn Error>> {
+        let (png, animation) = (self.png_due(step), self.animation_due(step));
+        if !png && !animation {
+            return Ok(());
+        }
+        let mut frame = draw(&self.renderer);
+        if self.caption {
+            frame.caption(&format!("step {}  cells {}", step, cells));
+        }
+        if let (true, Some(dir)) = (png, &self.png_dir) {
+            frame.write_png(&dir.join(format!("frame_{}.png", step)))?;
+        }
+        if let (true, Some(a)) = (animation, &mut self.animation) {
+            a.push(&frame)?;
+        }
+        Ok(())
+    }
+
+    pub fn finish(self) -> Result<(), Box<dyn Error>> {
+        self.animation.map_or(Ok(()), |a| a.finish())
+    }
+}
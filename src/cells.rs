@@ -1,132 +1,72 @@
 use crate::common::*;
-use ndarray::{ArrayView1, Array1, Array2, Axis, s, Ix1};
+use crate::config::SensorSettings;
+use crate::controller::{Controller, Mlp};
+use ndarray::{ArrayView1, Array1, Array2, Axis, s};
 use ndarray;
-use rand::rng;
-use rand_distr::{Distribution, Normal};
 use std::path::Path;
 use std::error::Error;
-use std::fs::{OpenOptions, File};
-use std::io::{BufWriter, Write, BufReader, BufRead};
-
-// #[derive(Debug)]
-pub struct Genome {
-    w1: Array2<f32>,
-    w2: Array2<f32>,
-    w3: Array2<f32>,
-    pub activation: Box<dyn Fn(&Array1<f32>) -> Array1<f32> + Sync + Send>,
+use std::fs::File;
+use std::io::{BufReader, BufRead};
+use std::str::FromStr;
+use std::sync::Arc;
+use uuid::Uuid;
+use serde::{Deserialize, Serialize};
+
+/// What a bud sees of a neighbouring cell.
+#[derive(Debug, Clone, Copy)]
+pub struct Neighbour {
+    pub kind: usize, // `CellKind::index`
+    pub kin: bool,
 }
-impl Genome {
-    /// Создать Genome с матрицами указанной формы и инициализировать
-    /// веса нормальным распределением N(mean, std).
-    pub fn random(
-        n_in: usize,
-        n_hidden1: usize,
-        n_hidden2: usize,
-        n_out: usize,
-        mean: f32,
-        std: f32,
-    ) -> Self {
-        let mut rng = rng();
-        let normal = Normal::new(mean, std).unwrap();
-
-        let total_w1 = n_hidden1 * n_in;
-        let total_w2 = n_hidden2 * n_hidden1;
-        let total_w3 = n_out * n_hidden2;
-
-        let w1_vec: Vec<f32> = (0..total_w1).map(|_| normal.sample(&mut rng)).collect();
-        let w2_vec: Vec<f32> = (0..total_w2).map(|_| normal.sample(&mut rng)).collect();
-        let w3_vec: Vec<f32> = (0..total_w3).map(|_| normal.sample(&mut rng)).collect();
-
-        let w1 = Array2::from_shape_vec((n_hidden1, n_in), w1_vec).unwrap();
-        let w2 = Array2::from_shape_vec((n_hidden2, n_hidden1), w2_vec).unwrap();
-        let w3 = Array2::from_shape_vec((n_out, n_hidden2), w3_vec).unwrap();
-
-        // ReLU in-place would be more efficient, но для совместимости возвращаем новый Array1
-        let relu = Box::new(|v: &Array1<f32>| -> Array1<f32> {
-            v.mapv(|x| if x > 0.0 { x } else { 0.0 })
-        });
-
-        Genome { w1, w2, w3, activation: relu }
-    }
-
-    pub fn clone(&self) -> Self {
-        let activation = Box::new(|v: &Array1<f32>| -> Array1<f32> {
-            v.mapv(|x| if x > 0.0 { x } else { 0.0 })
-        });
-
-        Genome {
-            w1: self.w1.clone(),
-            w2: self.w2.clone(),
-            w3: self.w3.clone(),
-            activation,
-        }
-    }
-
-    pub fn mutate(&self) -> Self {
-        let mut rng = rng();
-        let mutation_std = 0.1;
-        let normal = Normal::new(0.0, mutation_std).unwrap();
-
-        // W1
-        let len1 = self.w1.len();
-        let mut noise1: Vec<f32> = Vec::with_capacity(len1);
-        for _ in 0..len1 {
-            noise1.push(normal.sample(&mut rng));
-        }
-        let noise1_arr = Array2::from_shape_vec(self.w1.raw_dim(), noise1).unwrap();
-        let new_w1 = &self.w1 + &noise1_arr;
-
-        // W2
-        let len2 = self.w2.len();
-        let mut noise2: Vec<f32> = Vec::with_capacity(len2);
-        for _ in 0..len2 {
-            noise2.push(normal.sample(&mut rng));
-        }
-        let noise2_arr = Array2::from_shape_vec(self.w2.raw_dim(), noise2).unwrap();
-        let new_w2 = &self.w2 + &noise2_arr;
-
-        // W3
-        let len3 = self.w3.len();
-        let mut noise3: Vec<f32> = Vec::with_capacity(len3);
-        for _ in 0..len3 {
-            noise3.push(normal.sample(&mut rng));
-        }
-        let noise3_arr = Array2::from_shape_vec(self.w3.raw_dim(), noise3).unwrap();
-        let new_w3 = &self.w3 + &noise3_arr;
-
-        let activation = Box::new(|v: &Array1<f32>| -> Array1<f32> {
-            v.mapv(|x| if x > 0.0 { x } else { 0.0 })
-        });
-
-        Genome {
-            w1: new_w1,
-            w2: new_w2,
-            w3: new_w3,
-            activation,
-        }
-    }
-}
-
 
 pub struct Input {
     pub organic_poisoning: Array2<f32>,
     pub electric_poisoning: Array2<f32>,
     pub energy: f32,
+    /// Cells on each side, in `Direction::all_directions` order.
+    pub neighbours: [Option<Neighbour>; 4],
+    pub life_left: f32,
+    /// Distance to the north, east, south and west edges, relative to the map size.
+    pub edge_distance: [f32; 4],
+    pub light: f32,
 }
 impl Input {
-    pub fn flatten(&self) -> Array1<f32> {
+    /// Genome input made of the sensors enabled in `sensors`, see `SensorSettings::input_size`.
+    pub fn flatten(&self, sensors: &SensorSettings) -> Array1<f32> {
+        let mut extra: Vec<f32> = Vec::new();
+        if sensors.neighbours {
+            for n in &self.neighbours {
+                let mut one_hot = [0f32; 3];
+                if let Some(n) = n { one_hot[n.kind] = 1.0; }
+                extra.extend(one_hot);
+            }
+        }
+        if sensors.kin {
+            extra.extend(self.neighbours.iter().map(|n| match n {
+                Some(n) if n.kin => 1f32,
+                _ => 0f32,
+            }));
+        }
+        if sensors.lifetime { extra.push(self.life_left); }
+        if sensors.edge_distance { extra.extend(self.edge_distance); }
+        if sensors.light { extra.push(self.light); }
+        if sensors.energy { extra.push(self.energy); }
+        let e = Array1::from_vec(extra);
+
+        if !sensors.pollution {
+            return e;
+        }
         let a_view: ArrayView1<f32> =
             self.organic_poisoning.view().into_shape_with_order(self.organic_poisoning.len()).unwrap();
         let b_view: ArrayView1<f32> =
             self.electric_poisoning.view().into_shape_with_order(self.electric_poisoning.len()).unwrap();
-        let e = Array1::from_vec(vec![self.energy]);
         // println!("input: ");
         // println!("{}, {}, {}", a_view, b_view, e);
         ndarray::concatenate(Axis(0), &[a_view, b_view, e.view()]).unwrap()
     }
 }
 
-#[derive(Debug)]
+#[derive(Debug, Serialize, Deserialize)]
 pub struct Producer {
     pub resource: ResourceType,
 }
@@ -139,29 +79,22 @@ pub struct Producer {
 
 // #[derive(Debug)]
 pub struct Storage {
-    pub genome: Genome,
+    /// Shared by every daughter that inherited it unmutated.
+    pub genome: Arc<dyn Controller>,
+    /// Hidden state of a recurrent genome, carried between steps and passed to daughters.
+    pub memory: Array1<f32>,
 }
 impl Storage {
-    pub fn get_decision(&self, input: Input) -> Vec<Action> {
-        let input_arr = input.flatten();
+    pub fn new(genome: Box<dyn Controller>) -> Self {
+        let memory = Array1::zeros(genome.state_size());
+        Self { genome: Arc::from(genome), memory }
+    }
 
-        // calculating NN result
-        let mut out: Array1<f32> = self.genome.w1.dot(&input_arr);
-        out = (self.genome.activation)(&out);
-        out = self.genome.w2.dot(&out);
-        out = (self.genome.activation)(&out);
-        let mat = self.genome.w3.dot(&out);
-        let flat: Array1<f32> = match mat.clone().into_dimensionality::<Ix1>() {
-            Ok(v) => v,
-            Err(_) => {
-                let v: Vec<f32> = mat.iter().cloned().collect();
-                if v.len() != 5*4 {
-                    panic!("expected length 20, got {}", v.len());
-                }
-                Array1::from(v)
-            }
-        };
-        out = flat;
+    /// Actions encoded in the genome output `out`, 4 scores per direction.
+    pub fn decode(out: ArrayView1<f32>) -> Vec<Action> {
+        if out.len() != 4*4 {
+            panic!("expected length 16, got {}", out.len());
+        }
 
         // codes: 
         // 0 - leaf
@@ -205,11 +138,37 @@ impl CellKind {
             Self::Conductor   => "conductor",
         }
     }
+
+    /// Name shown to the user; producers are named by their resource.
+    pub fn name(&self) -> &'static str {
+        match self {
+            Self::Producer(p) => match p.resource {
+                ResourceType::Solar       => "leaf",
+                ResourceType::Organic     => "root",
+                ResourceType::Electricity => "antenna",
+            },
+            Self::Conductor   => "conductor",
+            Self::Storage(_)  => "bud",
+        }
+    }
+
+    /// Dense code of the kind: 0 - producer, 1 - conductor, 2 - bud.
+    pub fn index(&self) -> usize {
+        match self {
+            Self::Producer(_) => 0,
+            Self::Conductor   => 1,
+            Self::Storage(_)  => 2,
+        }
+    }
 }
 
 // #[derive(Debug)]
 pub struct Cell {
     pub kind: CellKind,
+    /// Bud this cell was grown by, shared with all its conductors and producers.
+    pub organism: Uuid,
+    /// Genome of that bud, see `LineageRecord`.
+    pub genome_id: Uuid,
     pub life_time: i16,
     pub pos: Coord,
     pub out_dir: Direction,
@@ -217,59 +176,6 @@ pub struct Cell {
 }
 
 impl Cell {
-    pub fn save(&self, save_path: &Path, overwrite: bool) -> Result<(), Box<dyn Error>> {
-        // main.txt (meta)
-        let meta_path = save_path.join("main.txt");
-        if !meta_path.exists() || overwrite {
-            let f = OpenOptions::new()
-                .create(true)
-                .write(true)
-                .truncate(true)
-                .open(&meta_path)?;
-            let mut w = BufWriter::new(f);
-            // Пример: kind, life_time, pos(x,y), out_dir, energy
-            writeln!(
-                w,
-                "{},{},{},{}",
-                self.kind.str(),
-                self.life_time,
-                self.pos.x, // предполагается, что Coord имеет поля x,y
-                self.pos.y
-            )?;
-            writeln!(w, "out_dir:{:?}", self.out_dir)?;
-            writeln!(w, "energy:{}", self.energy)?;
-            w.flush()?;
-        }
-
-        // genomes directory
-        let genomes_dir = save_path.join("genomes");
-        ensure_dir(genomes_dir.as_path()).expect("cannot ensure genome directory");
-
-        // If cell has a Genome (Storage), save matrices
-        if let CellKind::Storage(storage) = &self.kind {
-            let genome_dir = genomes_dir;
-            ensure_dir(genome_dir.as_path()).expect("cannot ensure storage directory");
-
-            let w1_path = genome_dir.join("w1.npy");
-            save_npy(&storage.genome.w1, w1_path.as_path())?;
-            let w2_path = genome_dir.join("w2.npy");
-            save_npy(&storage.genome.w2, w2_path.as_path())?;
-            let w3_path = genome_dir.join("w3.npy");
-            save_npy(&storage.genome.w3, w3_path.as_path())?;
-            // optionally save activation choice as text
-        }
-
-        // If Producer or other kinds, save their data if needed
-        if let CellKind::Producer(prod) = &self.kind {
-            let prod_path = save_path.join("producer.txt");
-            let f = File::create(prod_path)?;
-            let mut bw = BufWriter::new(f);
-            writeln!(bw, "resource:{:?}", prod.resource)?;
-        }
-
-        Ok(())
-    }
-
     pub fn load(save_path: &Path) -> Result<Self, Box<dyn Error>> {
         // main.txt
         let meta_path = save_path.join("main.txt");
@@ -312,8 +218,7 @@ impl Cell {
                     // Удалим возможные символы "Direction", '"' и т.д.
                     let cleaned = v.replace("Direction::", "")
                                    .replace("Direction(", "")
-                                   .replace(')', "")
-                                   .replace('"', "")
+                                   .replace([')', '"'], "")
                                    .trim()
                                    .to_string();
                     Direction::from_str(&cleaned)?
@@ -388,12 +293,8 @@ impl Cell {
                 let w2: Array2<f32> = load_npy(&w2_path)?;
                 let w3: Array2<f32> = load_npy(&w3_path)?;
 
-                let activation = Box::new(|v: &Array1<f32>| -> Array1<f32> {
-                    v.mapv(|x| if x > 0.0 { x } else { 0.0 })
-                });
-
-                let genome = Genome { w1, w2, w3, activation };
-                CellKind::Storage(Storage { genome })
+                let genome = Box::new(Mlp::from_weights(vec![w1, w2, w3]));
+                CellKind::Storage(Storage::new(genome))
             }
             "conductor" => CellKind::Conductor,
             other => {
@@ -401,8 +302,11 @@ impl Cell {
             }
         };
 
+        // old saves have no ids
         Ok(Cell {
             kind,
+            organism: Uuid::nil(),
+            genome_id: Uuid::nil(),
             life_time,
             pos,
             out_dir,
@@ -0,0 +1,192 @@
+use clap::{Args, Parser, Subcommand};
+use std::path::PathBuf;
+
+use crate::map::Topology;
+use crate::inspect::KindFilter;
+use crate::render::{Crop, Layer};
+
+#[derive(Parser, Debug)]
+#[command(name = "plants_war", version, about = "Plants war evolution simulation")]
+pub struct Cli {
+    #[command(subcommand)]
+    pub command: Command,
+}
+
+#[derive(Subcommand, Debug)]
+pub enum Command {
+    /// Generate a fresh world and run it
+    New {
+        #[command(flatten)]
+        world: WorldArgs,
+        #[command(flatten)]
+        run: RunArgs,
+    },
+    /// Continue a run from a saved state directory
+    Resume {
+        /// Directory written by `save_state` (e.g. ./saves_back)
+        dir: PathBuf,
+        #[command(flatten)]
+        run: RunArgs,
+    },
+    /// Resume from `--from` if a state is saved there, otherwise generate a new world
+    Run {
+        /// Saved state to try first
+        #[arg(long, default_value = "./saves_back")]
+        from: PathBuf,
+        #[command(flatten)]
+        world: WorldArgs,
+        #[command(flatten)]
+        run: RunArgs,
+    },
+    /// Print a summary of a saved state, list or describe its cells
+    Inspect {
+        /// Directory written by `save_state` (e.g. ./saves_back)
+        dir: PathBuf,
+        #[command(flatten)]
+        query: QueryArgs,
+    },
+    /// Draw the trajectory of a finished run into PNG frames or an animation
+    Animate {
+        /// Directory of the views (the `--out-dir` of the run, e.g. ./saves)
+        dir: PathBuf,
+        /// File name of the trajectory, without `.traj`
+        #[arg(long, default_value = "snap")]
+        snap_name: String,
+        #[command(flatten)]
+        render: RenderArgs,
+    },
+    /// Pack the per-step CSV views of older runs into a trajectory file
+    Convert {
+        /// Directory with `<snap-name>_meta.txt` and `<snap-name>_<step>.csv`
+        dir: PathBuf,
+        /// File name prefix of the views
+        #[arg(long, default_value = "snap")]
+        snap_name: String,
+    },
+}
+
+/// Parameters of a freshly generated world; flags override the config file.
+#[derive(Args, Debug)]
+pub struct WorldArgs {
+    /// World file (.toml or .json) with the world and simulation rules
+    #[arg(long)]
+    pub config: Option<PathBuf>,
+    /// Map width in cells [default: 1024]
+    #[arg(long)]
+    pub width: Option<usize>,
+    /// Map height in cells [default: 1024]
+    #[arg(long)]
+    pub height: Option<usize>,
+    /// Number of buds scattered over the map at start [default: 5000]
+    #[arg(long = "cells")]
+    pub n_cells: Option<usize>,
+    /// What lies past the map border: `bounded` or `torus` [default: bounded]
+    #[arg(long)]
+    pub topology: Option<Topology>,
+    /// Life time of every new cell, in steps [default: 150]
+    #[arg(long)]
+    pub lifetime: Option<i16>,
+    /// Master seed of the run (random if omitted)
+    #[arg(long)]
+    pub seed: Option<u64>,
+}
+
+/// Parameters of the main loop and its outputs.
+#[derive(Args, Debug)]
+pub struct RunArgs {
+    /// Number of steps to simulate
+    #[arg(long, default_value_t = 3000)]
+    pub steps: u64,
+    /// Save the full state every N steps (defaults to steps / 10)
+    #[arg(long)]
+    pub save_interval: Option<u64>,
+    /// Directory for the trajectory of the run; the state goes to `<out-dir>_back`
+    #[arg(long)]
+    pub out_dir: Option<String>,
+    /// File name of the trajectory, without `.traj`
+    #[arg(long)]
+    pub snap_name: Option<String>,
+    /// Write every event of the run (births, deaths, moves, mutations, energy flows) to this CSV
+    #[arg(long)]
+    pub events: Option<PathBuf>,
+    /// Watch the run in the terminal instead of a progress bar
+    #[arg(long)]
+    pub tui: bool,
+    #[command(flatten)]
+    pub render: RenderArgs,
+}
+
+/// Pictures of the world drawn during a run.
+#[derive(Args, Debug)]
+pub struct RenderArgs {
+    /// Animation of the run: `.gif`, or `.y4m` for uncompressed video
+    #[arg(long)]
+    pub animation: Option<PathBuf>,
+    /// Add a frame to the animation every N steps
+    #[arg(long, default_value_t = 1)]
+    pub animation_every: usize,
+    /// Frames per second of the animation
+    #[arg(long, default_value_t = 10)]
+    pub fps: u16,
+    /// Directory for PNG frames; nothing is drawn without it
+    #[arg(long)]
+    pub png_dir: Option<PathBuf>,
+    /// Draw a frame every N steps
+    #[arg(long, default_value_t = 1)]
+    pub png_every: usize,
+    /// Side of the square drawn for one map cell, in pixels
+    #[arg(long, default_value_t = 1)]
+    pub png_scale: usize,
+    /// Pollution layer to draw under the cells: `organics` or `electric` (repeatable)
+    #[arg(long)]
+    pub overlay: Vec<Layer>,
+    /// TOML file with the colours, e.g. `bud = [230, 60, 60]`
+    #[arg(long)]
+    pub palette: Option<PathBuf>,
+    /// Draw only this region of the map: `x,y,width,height` in cells
+    #[arg(long)]
+    pub crop: Option<Crop>,
+    /// Write the step and the number of cells in the corner of every picture
+    #[arg(long)]
+    pub caption: bool,
+}
+
+/// What `inspect` prints. The filters narrow the summary as well as the list.
+#[derive(Args, Debug)]
+pub struct QueryArgs {
+    /// List the matching cells
+    #[arg(long)]
+    pub cells: bool,
+    /// Only cells in this rectangle: `x,y,width,height`
+    #[arg(long)]
+    pub rect: Option<Crop>,
+    /// Only cells of this kind: producer, leaf, root, antenna, conductor or bud
+    #[arg(long)]
+    pub kind: Option<KindFilter>,
+    #[arg(long)]
+    pub min_energy: Option<f32>,
+    #[arg(long)]
+    pub max_energy: Option<f32>,
+    #[arg(long)]
+    pub min_life: Option<i16>,
+    #[arg(long)]
+    pub max_life: Option<i16>,
+    /// Describe the cell at `x,y`, with the shapes and norms of its genome
+    #[arg(long, value_parser = parse_xy)]
+    pub cell: Option<(i64, i64)>,
+    /// Print JSON instead of text
+    #[arg(long)]
+    pub json: bool,
+}
+
+fn parse_xy(s: &str) -> Result<(i64, i64), String> {
+    let bad = || format!("bad cell {:?} (expected x,y)", s);
+    let (x, y) = s.split_once(',').ok_or_else(bad)?;
+    Ok((x.trim().parse().map_err(|_| bad())?, y.trim().parse().map_err(|_| bad())?))
+}
+
+impl RunArgs {
+    pub fn save_interval(&self) -> u64 {
+        self.save_interval.unwrap_or(self.steps / 10).max(1)
+    }
+}
@@ -2,11 +2,41 @@ use ndarray::{Array2};
 use serde::de::value::Error;
 use std::path::Path;
 use ndarray_npy::{write_npy, WriteNpyError, ReadNpyError, ReadNpyExt};
-use std::io;
+use std::io::{self, Write};
 use std::fs;
 use std::fs::File;
+use std::str::FromStr;
+use rand::{Rng, SeedableRng};
+use rand_chacha::ChaCha8Rng;
+use serde::{Deserialize, Serialize};
+use uuid::Uuid;
 
-#[derive(Debug)]
+/// Generator behind every random draw of the simulation.
+pub type SimRng = ChaCha8Rng;
+
+/// Random (v4) id drawn from `rng`, so ids are reproducible like everything else.
+pub fn new_id(rng: &mut impl Rng) -> Uuid {
+    uuid::Builder::from_random_bytes(rng.random()).into_uuid()
+}
+
+/// Stream ids for `stream_rng`, one per independent consumer of randomness.
+pub const STREAM_GENERATION: u64 = 1;
+pub const STREAM_STEP: u64 = 2;
+
+/// Derive a reproducible generator for item `index` of `stream` from the master seed.
+pub fn stream_rng(seed: u64, stream: u64, index: u64) -> SimRng {
+    // splitmix64 finalizer, so that neighbouring indices give unrelated keys
+    let mut z = seed ^ index.wrapping_mul(0x9E37_79B9_7F4A_7C15);
+    z = (z ^ (z >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+    z = (z ^ (z >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+    z ^= z >> 31;
+
+    let mut rng = SimRng::seed_from_u64(z);
+    rng.set_stream(stream);
+    rng
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Coord { pub x: i64, pub y: i64 }
 impl Coord {
     pub fn to_tuple_yx(&self) -> (i64, i64) {
@@ -15,10 +45,6 @@ impl Coord {
     pub fn to_tuple_xy(&self) -> (i64, i64) {
         (self.x, self.y)
     }
-    pub fn clone(&self) -> Coord {
-        Coord { x: self.x, y: self.y }
-    }
-    
     pub fn shift_inplace(&mut self, dir: &Direction) {
         match dir {
             Direction::North => { self.y -= 1 },
@@ -31,15 +57,17 @@ impl Coord {
     pub fn shift(&self, dir: &Direction) -> Coord {
         let mut coord = self.clone();
         coord.shift_inplace(dir);
-        return coord;
+        coord
     }
 }
 
 #[derive(Clone)]
-#[derive(Debug)]
+#[derive(Debug, Serialize, Deserialize)]
 pub enum Direction { North, East, South, West }
-impl Direction {
-    pub fn from_str(s: &str) -> Result<Direction, Error> {
+impl FromStr for Direction {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Direction, Error> {
         match s {
             "North" => Ok(Direction::North),
             "South" => Ok(Direction::South),
@@ -48,6 +76,9 @@ impl Direction {
             _ => panic!("wrong direction in str-dir conversion!")
         }
     }
+}
+
+impl Direction {
     pub fn all_directions() -> [Direction; 4] {
         [Direction::North, Direction::East, Direction::South, Direction::West]
     }
@@ -62,11 +93,13 @@ impl Direction {
     }
 }
 
-#[derive(Debug)]
-pub enum ResourceType { Solar, Organic, Electricity }
+#[derive(Debug, Default, Clone, Copy, Serialize, Deserialize)]
+pub enum ResourceType { #[default] Solar, Organic, Electricity }
+
+impl FromStr for ResourceType {
+    type Err = Error;
 
-impl ResourceType {
-    pub fn from_str(s: &str) -> Result<Self, Error> {
+    fn from_str(s: &str) -> Result<Self, Error> {
         match s {
             "Solar"       => Ok(Self::Solar),
             "Electricity" => Ok(Self::Electricity),
@@ -74,12 +107,9 @@ impl ResourceType {
             _ => panic!("there is not such resource type!")
         }
     }
-
-    pub fn default() -> Self {
-        return Self::Solar;
-    }
 }
 
+
 pub struct Action(pub Direction, pub u8);
 
 pub fn save_npy<T: ndarray_npy::WritableElement>(arr: &Array2<T>, path: &Path) -> Result<(), WriteNpyError> {
@@ -87,7 +117,7 @@ pub fn save_npy<T: ndarray_npy::WritableElement>(arr: &Array2<T>, path: &Path) -
 }
 
 pub fn load_npy<T: ndarray_npy::ReadableElement>(path: &Path) -> Result<Array2<T>, ReadNpyError> {
-    let f = File::open(path).map_err(|e| ReadNpyError::Io(e))?;
+    let f = File::open(path).map_err(ReadNpyError::Io)?;
     Array2::read_npy(f)
 }
 
@@ -101,4 +131,18 @@ pub fn ensure_dir(path: &Path) -> io::Result<()> {
     } else {
         fs::create_dir_all(path)
     }
-}
\ No newline at end of file
+}
+
+/// Append `rows` to the CSV at `path`, writing `header` first if the file is new.
+pub fn append_csv(path: &Path, header: &str, rows: impl IntoIterator<Item = String>) -> io::Result<()> {
+    let is_new = !path.exists();
+    let f = fs::OpenOptions::new().create(true).append(true).open(path)?;
+    let mut w = io::BufWriter::new(f);
+    if is_new {
+        writeln!(w, "{}", header)?;
+    }
+    for row in rows {
+        writeln!(w, "{}", row)?;
+    }
+    w.flush()
+}
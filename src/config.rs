@@ -0,0 +1,454 @@
+use serde::{Deserialize, Serialize};
+use crate::controller::{Activation, ControllerKind};
+use crate::map::{Padding, Topology};
+use std::collections::HashMap;
+use std::error::Error;
+use std::fs::{self, File};
+use std::io::{BufRead, BufReader};
+use std::path::Path;
+
+/// Everything a world file can describe: the initial world and the rules it runs by.
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+#[serde(default, deny_unknown_fields)]
+pub struct Config {
+    pub world: WorldSettings,
+    pub simulation: SimulationSettings,
+    /// Whether the file set `simulation.seed`; the default seed is only a placeholder.
+    #[serde(skip)]
+    pub seeded: bool,
+}
+
+/// Just the seed of a world file, to tell a missing `simulation.seed` from an explicit one.
+#[derive(Deserialize, Default)]
+#[serde(default)]
+struct SeedProbe {
+    simulation: SimulationSeed,
+}
+
+#[derive(Deserialize, Default)]
+#[serde(default)]
+struct SimulationSeed {
+    seed: Option<u64>,
+}
+
+/// Shape and population of a freshly generated world.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(default, deny_unknown_fields)]
+pub struct WorldSettings {
+    pub width: usize,
+    pub height: usize,
+    /// Buds scattered over the map at start.
+    pub n_cells: usize,
+    /// `bounded` or `torus`; kept by the map for the whole run.
+    pub topology: Topology,
+}
+
+impl Default for WorldSettings {
+    fn default() -> Self {
+        Self { width: 1024, height: 1024, n_cells: 5000, topology: Topology::Bounded }
+    }
+}
+
+/// Rules of the simulation; saved together with the state.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(default, deny_unknown_fields)]
+pub struct SimulationSettings {
+    /// Master seed every random stream of the run is derived from.
+    pub seed: u64,
+    pub life_time: i16,
+    pub energy: EnergySettings,
+    pub upkeep: UpkeepSettings,
+    pub corpse: CorpseSettings,
+    pub pollution: PollutionSettings,
+    pub mutation: MutationSettings,
+    pub genome: GenomeSettings,
+    pub sensors: SensorSettings,
+    pub light: LightSettings,
+}
+
+impl Default for SimulationSettings {
+    fn default() -> Self {
+        Self {
+            seed: 0,
+            life_time: 150,
+            energy: EnergySettings::default(),
+            upkeep: UpkeepSettings::default(),
+            corpse: CorpseSettings::default(),
+            pollution: PollutionSettings::default(),
+            mutation: MutationSettings::default(),
+            genome: GenomeSettings::default(),
+            sensors: SensorSettings::default(),
+            light: LightSettings::default(),
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(default, deny_unknown_fields)]
+pub struct EnergySettings {
+    /// Cost of growing a leaf, root or antenna.
+    pub producer_cost: f32,
+    /// Cost of moving a bud or budding a daughter.
+    pub storage_cost: f32,
+    pub solar_yield: f32,
+    pub organic_yield: f32,
+    pub electric_yield: f32,
+    /// Pollution removed from the 3x3 area around a root/antenna per step.
+    pub resource_drain: f32,
+    /// Share of the prey's energy a bud gets when it eats a cell.
+    pub hunt_efficiency: f32,
+    /// Share of `storage_cost` a daughter bud starts with.
+    pub daughter_share: f32,
+    /// Share of the energy lost on every hop from a producer or conductor to the bud.
+    pub transport_loss: f32,
+}
+
+impl Default for EnergySettings {
+    fn default() -> Self {
+        Self {
+            producer_cost: 0.1,
+            storage_cost: 0.25,
+            solar_yield: 0.1,
+            organic_yield: 0.1,
+            electric_yield: 0.1,
+            resource_drain: 0.2,
+            hunt_efficiency: 0.7,
+            daughter_share: 0.8,
+            transport_loss: 0.02,
+        }
+    }
+}
+
+/// Energy every living cell costs its plant per step; a bud that cannot pay starves.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(default, deny_unknown_fields)]
+pub struct UpkeepSettings {
+    pub producer: f32,
+    pub conductor: f32,
+    pub storage: f32,
+}
+
+impl Default for UpkeepSettings {
+    fn default() -> Self {
+        Self { producer: 0.01, conductor: 0.005, storage: 0.02 }
+    }
+}
+
+/// Organics a dead cell leaves, spread over the 3x3 area around it.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(default, deny_unknown_fields)]
+pub struct CorpseSettings {
+    /// Body matter of each kind.
+    pub producer: f32,
+    pub conductor: f32,
+    pub storage: f32,
+    /// Share of the energy stored in the cell that turns into organics too.
+    pub energy_share: f32,
+}
+
+impl Default for CorpseSettings {
+    fn default() -> Self {
+        Self { producer: 0.3, conductor: 0.3, storage: 0.5, energy_share: 1.0 }
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(default, deny_unknown_fields)]
+pub struct PollutionSettings {
+    /// Share of the pollution that decays every step.
+    pub decrease: f32,
+    /// Level above which cells die of pollution.
+    pub critical_lvl: f32,
+    /// Share of the pollution of a cell that spreads evenly to its 4 neighbours every step.
+    pub diffusion: f32,
+    /// Shift of the whole field per step, `[x, y]` in cells.
+    pub wind: [f32; 2],
+    /// Share of the organics that decomposes into the electric layer every step.
+    pub decomposition: f32,
+}
+
+impl Default for PollutionSettings {
+    fn default() -> Self {
+        Self { decrease: 0.01, critical_lvl: 15.0, diffusion: 0.1, wind: [0.0, 0.0], decomposition: 0.02 }
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(default, deny_unknown_fields)]
+pub struct MutationSettings {
+    /// Chance that a daughter bud gets a mutated genome.
+    pub probability: f64,
+    /// Std of the gaussian noise added to every weight.
+    pub std: f32,
+}
+
+impl Default for MutationSettings {
+    fn default() -> Self {
+        Self { probability: 0.15, std: 0.1 }
+    }
+}
+
+/// Architecture and initialisation of the genomes of the first generation.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(default, deny_unknown_fields)]
+pub struct GenomeSettings {
+    pub kind: ControllerKind,
+    /// Size of the recurrent memory of `elman` controllers.
+    pub memory: usize,
+    /// Sizes of the hidden layers, input to output.
+    pub hidden: Vec<usize>,
+    pub activation: Activation,
+    pub output_activation: Activation,
+    pub init_mean: f32,
+    pub init_std: f32,
+}
+
+impl Default for GenomeSettings {
+    fn default() -> Self {
+        Self {
+            kind: ControllerKind::Mlp,
+            memory: 16,
+            hidden: vec![128, 256],
+            activation: Activation::Relu,
+            output_activation: Activation::Identity,
+            init_mean: 0.0,
+            init_std: 0.1,
+        }
+    }
+}
+
+/// What a bud perceives; every enabled sensor adds its values to the genome input.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(default, deny_unknown_fields)]
+pub struct SensorSettings {
+    /// Organic and electric pollution in the window around the bud.
+    pub pollution: bool,
+    /// The pollution window spans `2 * radius + 1` cells each way.
+    pub radius: usize,
+    /// Fill of the window past the map border; ignored on a torus, where the window wraps.
+    pub padding: Padding,
+    /// Energy stored in the bud.
+    pub energy: bool,
+    /// Kind of the cell on each side (producer, conductor, bud), all zeros if empty.
+    pub neighbours: bool,
+    /// Whether the cell on each side belongs to the same organism.
+    pub kin: bool,
+    /// Remaining share of the bud's life time.
+    pub lifetime: bool,
+    /// Distance to each map edge relative to the map size.
+    pub edge_distance: bool,
+    /// Sunlight falling on the bud.
+    pub light: bool,
+}
+
+impl Default for SensorSettings {
+    fn default() -> Self {
+        Self {
+            pollution: true,
+            radius: 2,
+            padding: Padding::Constant(-1.0),
+            energy: true,
+            neighbours: false,
+            kin: false,
+            lifetime: false,
+            edge_distance: false,
+            light: false,
+        }
+    }
+}
+
+impl SensorSettings {
+    /// Side of the pollution window.
+    pub fn window(&self) -> usize {
+        2 * self.radius + 1
+    }
+
+    /// Number of genome inputs the enabled sensors produce.
+    pub fn input_size(&self) -> usize {
+        let mut n = 0;
+        if self.pollution { n += 2 * self.window() * self.window(); }
+        if self.neighbours { n += 4 * 3; }
+        if self.kin { n += 4; }
+        if self.lifetime { n += 1; }
+        if self.edge_distance { n += 4; }
+        if self.light { n += 1; }
+        if self.energy { n += 1; }
+        n
+    }
+}
+
+/// Sunlight: the `solar_yield` of a leaf is scaled by the light on its cell.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(default, deny_unknown_fields)]
+pub struct LightSettings {
+    /// Steps in a day; 0 keeps it always noon.
+    pub day_length: usize,
+    /// Steps in a year; 0 turns the seasons off.
+    pub year_length: usize,
+    /// Share of the daylight left at night.
+    pub night: f32,
+    /// Light lost from the equator to the poles (top and bottom rows).
+    pub latitude: f32,
+    /// How far the brightest latitude moves north and south over the year, in half map heights.
+    pub tilt: f32,
+    /// Share of the light a leaf takes from the cell north of it.
+    pub shade: f32,
+}
+
+impl Default for LightSettings {
+    fn default() -> Self {
+        Self { day_length: 48, year_length: 1200, night: 0.2, latitude: 0.5, tilt: 0.3, shade: 0.5 }
+    }
+}
+
+fn check(ok: bool, msg: &str) -> Result<(), Box<dyn Error>> {
+    if ok { Ok(()) } else { Err(format!("invalid config: {}", msg).into()) }
+}
+
+fn parse_by_extension<T: for<'de> Deserialize<'de>>(path: &Path) -> Result<T, Box<dyn Error>> {
+    let text = fs::read_to_string(path)?;
+    match path.extension().and_then(|e| e.to_str()) {
+        Some("toml") => Ok(toml::from_str(&text)?),
+        Some("json") => Ok(serde_json::from_str(&text)?),
+        _ => Err(format!("unknown config format of {:?} (expected .toml or .json)", path).into()),
+    }
+}
+
+impl Config {
+    /// Load a `.toml` or `.json` world file; missing fields take their defaults.
+    pub fn load(path: &Path) -> Result<Self, Box<dyn Error>> {
+        let mut config: Self = parse_by_extension(path)?;
+        config.seeded = parse_by_extension::<SeedProbe>(path)?.simulation.seed.is_some();
+        config.validate()?;
+        Ok(config)
+    }
+
+    pub fn validate(&self) -> Result<(), Box<dyn Error>> {
+        check(self.world.width > 0 && self.world.height > 0, "world size must be positive")?;
+        // smaller tori make a cell its own neighbour
+        check(self.world.topology != Topology::Torus || (self.world.width >= 3 && self.world.height >= 3),
+              "a torus world must be at least 3x3")?;
+        self.simulation.validate()
+    }
+}
+
+impl SimulationSettings {
+    pub fn validate(&self) -> Result<(), Box<dyn Error>> {
+        let e = &self.energy;
+        check(self.life_time > 0, "life_time must be positive")?;
+        check(e.producer_cost >= 0.0 && e.storage_cost >= 0.0, "energy costs must be non-negative")?;
+        check(e.solar_yield >= 0.0 && e.organic_yield >= 0.0 && e.electric_yield >= 0.0,
+              "energy yields must be non-negative")?;
+        check(e.resource_drain >= 0.0, "energy.resource_drain must be non-negative")?;
+        check((0.0..=1.0).contains(&e.hunt_efficiency), "energy.hunt_efficiency must be in [0, 1]")?;
+        check((0.0..=1.0).contains(&e.daughter_share), "energy.daughter_share must be in [0, 1]")?;
+        check((0.0..=1.0).contains(&e.transport_loss), "energy.transport_loss must be in [0, 1]")?;
+        let u = &self.upkeep;
+        check(u.producer >= 0.0 && u.conductor >= 0.0 && u.storage >= 0.0, "upkeep costs must be non-negative")?;
+        let c = &self.corpse;
+        check(c.producer >= 0.0 && c.conductor >= 0.0 && c.storage >= 0.0, "corpse matter must be non-negative")?;
+        check((0.0..=1.0).contains(&c.energy_share), "corpse.energy_share must be in [0, 1]")?;
+        check((0.0..=1.0).contains(&self.pollution.decomposition), "pollution.decomposition must be in [0, 1]")?;
+        check((0.0..=1.0).contains(&self.pollution.decrease), "pollution.decrease must be in [0, 1]")?;
+        check((0.0..=1.0).contains(&self.pollution.diffusion), "pollution.diffusion must be in [0, 1]")?;
+        check(self.pollution.wind.iter().all(|w| w.is_finite()), "pollution.wind must be finite")?;
+        check(self.pollution.critical_lvl > 0.0, "pollution.critical_lvl must be positive")?;
+        check((0.0..=1.0).contains(&self.mutation.probability), "mutation.probability must be in [0, 1]")?;
+        check(self.mutation.std.is_finite() && self.mutation.std >= 0.0, "mutation.std must be non-negative")?;
+        check(self.genome.hidden.iter().all(|&n| n > 0), "genome layers must not be empty")?;
+        check(self.sensors.input_size() > 0, "at least one sensor must be enabled")?;
+        if let Padding::Constant(v) = self.sensors.padding {
+            check(v.is_finite(), "sensors.padding value must be finite")?;
+        }
+        check(self.genome.kind != ControllerKind::Elman || self.genome.memory > 0,
+              "genome.memory must be positive for elman controllers")?;
+        let l = &self.light;
+        check((0.0..=1.0).contains(&l.night), "light.night must be in [0, 1]")?;
+        check((0.0..=1.0).contains(&l.latitude), "light.latitude must be in [0, 1]")?;
+        check((0.0..=1.0).contains(&l.tilt), "light.tilt must be in [0, 1]")?;
+        check((0.0..=1.0).contains(&l.shade), "light.shade must be in [0, 1]")?;
+        check(self.genome.init_std.is_finite() && self.genome.init_std >= 0.0,
+              "genome.init_std must be non-negative")?;
+        Ok(())
+    }
+
+    pub fn load(path: &Path) -> Result<Self, Box<dyn Error>> {
+        let settings: Self = parse_by_extension(path)?;
+        settings.validate()?;
+        Ok(settings)
+    }
+
+    /// Read `simulation_settings.txt` of older saves; everything it lacks keeps its default.
+    pub fn load_legacy(path: &Path) -> Result<Self, Box<dyn Error>> {
+        let f = File::open(path)?;
+        let mut reader = BufReader::new(f);
+        let mut line = String::new();
+        let mut settings = Self::default();
+
+        // life_time
+        line.clear();
+        if reader.read_line(&mut line)? == 0 {
+            return Err("simulation settings: missing life_time".into());
+        }
+        settings.life_time = line.trim().parse()?;
+
+        // polution_increase (corpses follow `corpse` now)
+        line.clear();
+        if reader.read_line(&mut line)? == 0 {
+            return Err("simulation settings: missing polution_increase".into());
+        }
+        let _: f32 = line.trim().parse()?;
+
+        // polution_decrease
+        line.clear();
+        if reader.read_line(&mut line)? == 0 {
+            return Err("simulation settings: missing polution_decrease".into());
+        }
+        settings.pollution.decrease = line.trim().parse()?;
+
+        // polution_critical_lvl
+        line.clear();
+        if reader.read_line(&mut line)? == 0 {
+            return Err("simulation settings: missing polution_critical_lvl".into());
+        }
+        settings.pollution.critical_lvl = line.trim().parse()?;
+
+        // seed (older saves go straight to energy_expanse and get seed 0)
+        line.clear();
+        reader.read_line(&mut line)?;
+        if let Ok(seed) = line.trim().parse() {
+            settings.seed = seed;
+            line.clear();
+        }
+
+        // remaining lines: energy_expanse key, val pairs
+        let mut energy_expanse: HashMap<String, f32> = HashMap::new();
+        loop {
+            if line.is_empty() && reader.read_line(&mut line)? == 0 {
+                break;
+            }
+            let s = line.trim().to_string();
+            line.clear();
+            let s = s.as_str();
+            if s.is_empty() {
+                continue;
+            }
+            // expect "key, val" or "key,val"
+            let parts: Vec<&str> = s.split(',').map(|p| p.trim()).collect();
+            if parts.len() != 2 {
+                return Err(format!("invalid energy_expanse line: {}", s).into());
+            }
+            let key = parts[0].to_string();
+            let val: f32 = parts[1].parse()?;
+            energy_expanse.insert(key, val);
+        }
+        if let Some(cost) = energy_expanse.get("producer") {
+            settings.energy.producer_cost = *cost;
+        }
+        if let Some(cost) = energy_expanse.get("storage") {
+            settings.energy.storage_cost = *cost;
+        }
+
+        Ok(settings)
+    }
+}
@@ -0,0 +1,403 @@
+use ndarray::{Array, Array1, Array2, ArrayViewD, Dimension};
+use rand::{Rng, RngCore};
+use rand_distr::{Distribution, Normal};
+use serde::{Deserialize, Serialize};
+
+use crate::config::GenomeSettings;
+
+/// Nonlinearity applied after a layer; stored with the layer, so it survives clone/mutate/save.
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum Activation {
+    Identity,
+    Relu,
+    Tanh,
+    Sigmoid,
+    /// Slope for negative inputs.
+    LeakyRelu(f32),
+}
+
+impl Activation {
+    pub fn apply(&self, x: f32) -> f32 {
+        match self {
+            Self::Identity => x,
+            Self::Relu => if x > 0.0 { x } else { 0.0 },
+            Self::Tanh => x.tanh(),
+            Self::Sigmoid => 1.0 / (1.0 + (-x).exp()),
+            Self::LeakyRelu(slope) => if x > 0.0 { x } else { slope * x },
+        }
+    }
+
+    pub fn apply_inplace<D: Dimension>(&self, v: &mut Array<f32, D>) {
+        if *self != Self::Identity {
+            v.mapv_inplace(|x| self.apply(x));
+        }
+    }
+
+    pub fn name(&self) -> String {
+        match self {
+            Self::Identity => "identity".to_string(),
+            Self::Relu => "relu".to_string(),
+            Self::Tanh => "tanh".to_string(),
+            Self::Sigmoid => "sigmoid".to_string(),
+            Self::LeakyRelu(slope) => format!("leaky_relu({})", slope),
+        }
+    }
+}
+
+/// Family of controllers a fresh world is seeded with.
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum ControllerKind {
+    Mlp,
+    Elman,
+}
+
+/// Brain of a bud: maps the flattened sensor input to action scores.
+pub trait Controller: Send + Sync {
+    fn n_inputs(&self) -> usize;
+    fn n_outputs(&self) -> usize;
+    /// Length of the memory the bud carries between steps; 0 for purely reactive controllers.
+    fn state_size(&self) -> usize { 0 }
+    /// Scores for `input`; recurrent controllers read and update `state` in place.
+    fn forward(&self, input: &Array1<f32>, state: &mut Array1<f32>) -> Array1<f32>;
+    /// `forward` for a batch, one input per row of `inputs` and one state per row of `states`.
+    /// Every row comes out the same whatever the batch size.
+    fn forward_batch(&self, inputs: &Array2<f32>, states: &mut Array2<f32>) -> Array2<f32>;
+    /// Copy with gaussian noise of `std` added to every parameter.
+    fn mutate(&self, rng: &mut dyn RngCore, std: f32) -> Box<dyn Controller>;
+    /// Serializable form, see `ControllerRecord`.
+    fn record(&self) -> ControllerRef<'_>;
+    /// Short human readable architecture, e.g. `mlp 51-128(relu)-16(identity)`.
+    fn describe(&self) -> String;
+    /// Every parameter tensor with its name, e.g. `layers.0.w`.
+    fn parameters(&self) -> Vec<(String, ArrayViewD<'_, f32>)>;
+    /// Check that the tensors fit together, so `forward` cannot fail on a loaded genome.
+    fn validate(&self) -> Result<(), String>;
+}
+
+/// Borrowed serialized form of every controller implementation.
+#[derive(Serialize)]
+pub enum ControllerRef<'a> {
+    Mlp(&'a Mlp),
+    Elman(&'a Elman),
+}
+
+/// Owned counterpart of `ControllerRef`, variant for variant.
+#[derive(Deserialize)]
+pub enum ControllerRecord {
+    Mlp(Mlp),
+    Elman(Elman),
+}
+
+impl ControllerRecord {
+    pub fn into_controller(self) -> Box<dyn Controller> {
+        match self {
+            Self::Mlp(mlp) => Box::new(mlp),
+            Self::Elman(elman) => Box::new(elman),
+        }
+    }
+}
+
+/// Random controller of the family and shape described by `settings`.
+pub fn random_controller(
+    rng: &mut impl Rng,
+    settings: &GenomeSettings,
+    n_in: usize,
+    n_out: usize,
+) -> Box<dyn Controller> {
+    match settings.kind {
+        ControllerKind::Mlp => {
+            let mut sizes = vec![n_in];
+            sizes.extend(&settings.hidden);
+            sizes.push(n_out);
+            Box::new(Mlp::random(rng, &sizes, settings.activation, settings.output_activation,
+                                 settings.init_mean, settings.init_std))
+        },
+        ControllerKind::Elman => Box::new(Elman::random(rng, n_in, settings.memory, n_out, settings)),
+    }
+}
+
+fn random_array(rng: &mut dyn RngCore, shape: (usize, usize), normal: &Normal<f32>) -> Array2<f32> {
+    let data: Vec<f32> = (0..shape.0 * shape.1).map(|_| normal.sample(rng)).collect();
+    Array2::from_shape_vec(shape, data).unwrap()
+}
+
+/// Fully connected layer `activation(w·x + b)`.
+#[derive(Serialize, Deserialize, Clone)]
+pub struct Dense {
+    pub w: Array2<f32>,
+    pub b: Array1<f32>,
+    pub activation: Activation,
+}
+
+impl Dense {
+    fn forward(&self, x: &Array1<f32>) -> Array1<f32> {
+        let mut out = self.w.dot(x);
+        out += &self.b;
+        self.activation.apply_inplace(&mut out);
+        out
+    }
+
+    /// `forward` of every row of `x`.
+    fn forward_batch(&self, x: &Array2<f32>) -> Array2<f32> {
+        let mut out = x.dot(&self.w.t());
+        out += &self.b;
+        self.activation.apply_inplace(&mut out);
+        out
+    }
+
+    /// Check the bias against the weights and, with `n_in`, the input size.
+    fn validate(&self, name: &str, n_in: usize) -> Result<(), String> {
+        if self.w.ncols() != n_in {
+            return Err(format!("{} takes {} inputs but gets {}", name, self.w.ncols(), n_in));
+        }
+        if self.b.len() != self.w.nrows() {
+            return Err(format!("{} has {} outputs but {} biases", name, self.w.nrows(), self.b.len()));
+        }
+        Ok(())
+    }
+
+    fn mutate(&self, rng: &mut dyn RngCore, normal: &Normal<f32>) -> Self {
+        let mut w = self.w.clone();
+        w.mapv_inplace(|v| v + normal.sample(rng));
+        let mut b = self.b.clone();
+        b.mapv_inplace(|v| v + normal.sample(rng));
+        Self { w, b, activation: self.activation }
+    }
+}
+
+/// Multilayer perceptron of any depth.
+#[derive(Serialize, Deserialize, Clone)]
+pub struct Mlp {
+    pub layers: Vec<Dense>,
+}
+
+impl Mlp {
+    /// Layers of `sizes[0] -> sizes[1] -> ... -> sizes[n]`, weights from N(mean, std), zero biases.
+    pub fn random(
+        rng: &mut impl Rng,
+        sizes: &[usize],
+        hidden_activation: Activation,
+        output_activation: Activation,
+        mean: f32,
+        std: f32,
+    ) -> Self {
+        assert!(sizes.len() >= 2, "mlp needs at least an input and an output size");
+        let normal = Normal::new(mean, std).unwrap();
+        let n_layers = sizes.len() - 1;
+        let layers = (0..n_layers).map(|i| {
+            let activation = if i + 1 == n_layers { output_activation } else { hidden_activation };
+            Dense {
+                w: random_array(rng, (sizes[i + 1], sizes[i]), &normal),
+                b: Array1::zeros(sizes[i + 1]),
+                activation,
+            }
+        }).collect();
+        Self { layers }
+    }
+
+    /// Network of the old fixed `w1/w2/w3` genome: ReLU hidden layers, linear output, no biases.
+    pub fn from_weights(weights: Vec<Array2<f32>>) -> Self {
+        let n_layers = weights.len();
+        let layers = weights.into_iter().enumerate().map(|(i, w)| {
+            let activation = if i + 1 == n_layers { Activation::Identity } else { Activation::Relu };
+            Dense { b: Array1::zeros(w.nrows()), w, activation }
+        }).collect();
+        Self { layers }
+    }
+
+    fn feed(&self, input: &Array1<f32>) -> Array1<f32> {
+        let mut layers = self.layers.iter();
+        let Some(first) = layers.next() else { return input.clone() };
+        let mut out = first.forward(input);
+        for layer in layers {
+            out = layer.forward(&out);
+        }
+        out
+    }
+
+    fn feed_batch(&self, inputs: &Array2<f32>) -> Array2<f32> {
+        let mut layers = self.layers.iter();
+        let Some(first) = layers.next() else { return inputs.clone() };
+        let mut out = first.forward_batch(inputs);
+        for layer in layers {
+            out = layer.forward_batch(&out);
+        }
+        out
+    }
+
+    fn mutated(&self, rng: &mut dyn RngCore, normal: &Normal<f32>) -> Self {
+        Self { layers: self.layers.iter().map(|l| l.mutate(rng, normal)).collect() }
+    }
+}
+
+impl Controller for Mlp {
+    fn n_inputs(&self) -> usize {
+        self.layers.first().map_or(0, |l| l.w.ncols())
+    }
+
+    fn n_outputs(&self) -> usize {
+        self.layers.last().map_or(0, |l| l.w.nrows())
+    }
+
+    fn forward(&self, input: &Array1<f32>, _state: &mut Array1<f32>) -> Array1<f32> {
+        self.feed(input)
+    }
+
+    fn forward_batch(&self, inputs: &Array2<f32>, _states: &mut Array2<f32>) -> Array2<f32> {
+        self.feed_batch(inputs)
+    }
+
+    fn mutate(&self, rng: &mut dyn RngCore, std: f32) -> Box<dyn Controller> {
+        let normal = Normal::new(0.0, std).unwrap();
+        Box::new(self.mutated(rng, &normal))
+    }
+
+    fn record(&self) -> ControllerRef<'_> {
+        ControllerRef::Mlp(self)
+    }
+
+    fn describe(&self) -> String {
+        let mut s = format!("mlp {}", self.n_inputs());
+        for l in &self.layers {
+            s += &format!("-{}({})", l.w.nrows(), l.activation.name());
+        }
+        s
+    }
+
+    fn parameters(&self) -> Vec<(String, ArrayViewD<'_, f32>)> {
+        self.layers.iter().enumerate().flat_map(|(i, l)| [
+            (format!("layers.{}.w", i), l.w.view().into_dyn()),
+            (format!("layers.{}.b", i), l.b.view().into_dyn()),
+        ]).collect()
+    }
+
+    fn validate(&self) -> Result<(), String> {
+        if self.layers.is_empty() {
+            return Err("mlp has no layers".to_string());
+        }
+        let mut n_in = self.n_inputs();
+        for (i, l) in self.layers.iter().enumerate() {
+            l.validate(&format!("layer {}", i), n_in)?;
+            n_in = l.w.nrows();
+        }
+        Ok(())
+    }
+}
+
+
+/// Elman network: a recurrent layer `h = act(w·x + u·h_prev + b)` feeding an MLP head.
+#[derive(Serialize, Deserialize, Clone)]
+pub struct Elman {
+    pub input: Dense,
+    pub recurrent: Array2<f32>,
+    pub head: Mlp,
+}
+
+impl Elman {
+    pub fn random(
+        rng: &mut impl Rng,
+        n_in: usize,
+        n_memory: usize,
+        n_out: usize,
+        settings: &GenomeSettings,
+    ) -> Self {
+        let normal = Normal::new(settings.init_mean, settings.init_std).unwrap();
+        let input = Dense {
+            w: random_array(rng, (n_memory, n_in), &normal),
+            b: Array1::zeros(n_memory),
+            activation: Activation::Tanh,
+        };
+        let recurrent = random_array(rng, (n_memory, n_memory), &normal);
+        let mut sizes = vec![n_memory];
+        sizes.extend(&settings.hidden);
+        sizes.push(n_out);
+        let head = Mlp::random(rng, &sizes, settings.activation, settings.output_activation,
+                               settings.init_mean, settings.init_std);
+        Self { input, recurrent, head }
+    }
+}
+
+impl Controller for Elman {
+    fn n_inputs(&self) -> usize {
+        self.input.w.ncols()
+    }
+
+    fn n_outputs(&self) -> usize {
+        self.head.n_outputs()
+    }
+
+    fn state_size(&self) -> usize {
+        self.recurrent.nrows()
+    }
+
+    fn forward(&self, input: &Array1<f32>, state: &mut Array1<f32>) -> Array1<f32> {
+        if state.len() != self.state_size() {
+            *state = Array1::zeros(self.state_size());
+        }
+        let mut h = self.input.w.dot(input);
+        h += &self.recurrent.dot(state);
+        h += &self.input.b;
+        self.input.activation.apply_inplace(&mut h);
+        let out = self.head.feed(&h);
+        *state = h;
+        out
+    }
+
+    fn forward_batch(&self, inputs: &Array2<f32>, states: &mut Array2<f32>) -> Array2<f32> {
+        if states.dim() != (inputs.nrows(), self.state_size()) {
+            *states = Array2::zeros((inputs.nrows(), self.state_size()));
+        }
+        let mut h = inputs.dot(&self.input.w.t());
+        h += &states.dot(&self.recurrent.t());
+        h += &self.input.b;
+        self.input.activation.apply_inplace(&mut h);
+        let out = self.head.feed_batch(&h);
+        *states = h;
+        out
+    }
+
+    fn mutate(&self, rng: &mut dyn RngCore, std: f32) -> Box<dyn Controller> {
+        let normal = Normal::new(0.0, std).unwrap();
+        let input = self.input.mutate(rng, &normal);
+        let mut recurrent = self.recurrent.clone();
+        recurrent.mapv_inplace(|v| v + normal.sample(rng));
+        let head = self.head.mutated(rng, &normal);
+        Box::new(Self { input, recurrent, head })
+    }
+
+    fn record(&self) -> ControllerRef<'_> {
+        ControllerRef::Elman(self)
+    }
+
+    fn describe(&self) -> String {
+        let head = self.head.describe();
+        let head = head.split_once('-').map_or("", |(_, rest)| rest);
+        format!("elman {}-{}({}, recurrent)-{}",
+                self.n_inputs(), self.state_size(), self.input.activation.name(), head)
+    }
+
+    fn parameters(&self) -> Vec<(String, ArrayViewD<'_, f32>)> {
+        let mut params = vec![
+            ("input.w".to_string(), self.input.w.view().into_dyn()),
+            ("input.b".to_string(), self.input.b.view().into_dyn()),
+            ("recurrent".to_string(), self.recurrent.view().into_dyn()),
+        ];
+        params.extend(self.head.parameters().into_iter().map(|(name, p)| (format!("head.{}", name), p)));
+        params
+    }
+
+    fn validate(&self) -> Result<(), String> {
+        self.input.validate("input layer", self.n_inputs())?;
+        let n = self.input.w.nrows();
+        if self.recurrent.dim() != (n, n) {
+            return Err(format!("recurrent matrix is {:?}, not {}x{}", self.recurrent.dim(), n, n));
+        }
+        self.head.validate().map_err(|e| format!("head: {}", e))?;
+        if self.head.n_inputs() != self.state_size() {
+            return Err(format!("head takes {} inputs but the memory holds {}", self.head.n_inputs(), self.state_size()));
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,135 @@
+use std::error::Error;
+use std::fs::File;
+use std::io::{BufWriter, Write};
+use std::path::Path;
+use uuid::Uuid;
+
+use crate::common::Coord;
+
+/// Why a cell left the world.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum DeathCause {
+    /// Its `life_time` ran out.
+    Age,
+    /// The pollution under it reached the critical level.
+    Pollution,
+    /// Cut off from every bud of its organism.
+    Isolation,
+    /// A bud with negative energy.
+    Starvation,
+    /// Eaten by a bud.
+    Eaten,
+}
+
+impl DeathCause {
+    pub const ALL: [DeathCause; 5] = [Self::Age, Self::Pollution, Self::Isolation, Self::Starvation, Self::Eaten];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Self::Age => "age",
+            Self::Pollution => "pollution",
+            Self::Isolation => "isolation",
+            Self::Starvation => "starvation",
+            Self::Eaten => "eaten",
+        }
+    }
+}
+
+/// Something that happened during a step; the simulation keeps the events of the last one.
+#[derive(Debug, Clone)]
+pub enum Event {
+    /// A cell grew: a leaf, root or antenna, the conductor a moving bud leaves behind, or a daughter bud.
+    Born {
+        pos: Coord,
+        /// `CellKind::index` of the new cell.
+        kind: usize,
+        organism: Uuid,
+    },
+    Died {
+        pos: Coord,
+        /// `CellKind::index` of the dead cell.
+        kind: usize,
+        cause: DeathCause,
+    },
+    /// A bud stepped to a neighbouring cell.
+    Moved {
+        organism: Uuid,
+        from: Coord,
+        to: Coord,
+    },
+    /// A daughter bud got a mutated copy of the genome.
+    Mutated {
+        organism: Uuid,
+        parent_genome: Uuid,
+        genome: Uuid,
+    },
+    /// Energy reached a bud: from its body, after the transport loss, or from its prey.
+    EnergyTransferred {
+        from: Coord,
+        to: Coord,
+        amount: f32,
+    },
+}
+
+impl Event {
+    pub fn name(&self) -> &'static str {
+        match self {
+            Self::Born { .. } => "born",
+            Self::Died { .. } => "died",
+            Self::Moved { .. } => "moved",
+            Self::Mutated { .. } => "mutated",
+            Self::EnergyTransferred { .. } => "energy",
+        }
+    }
+}
+
+/// Subscriber to the events of a simulation, for logging, statistics or pictures. The
+/// simulation is shared with the worker threads, so observers have to be `Send + Sync`.
+pub trait Observer: Send + Sync {
+    /// Called after every step, once per event of the step, in the order they happened.
+    fn on_event(&mut self, step: usize, event: &Event);
+}
+
+impl<F: FnMut(usize, &Event) + Send + Sync> Observer for F {
+    fn on_event(&mut self, step: usize, event: &Event) {
+        self(step, event)
+    }
+}
+
+/// Observer writing every event as a CSV row; the columns an event has no use for stay empty.
+pub struct EventLog {
+    out: BufWriter<File>,
+}
+
+impl EventLog {
+    pub fn create(path: &Path) -> Result<Self, Box<dyn Error>> {
+        let mut out = BufWriter::new(File::create(path)?);
+        writeln!(out, "step,event,x,y,to_x,to_y,kind,cause,organism,genome,parent_genome,amount")?;
+        Ok(Self { out })
+    }
+
+    fn write(&mut self, step: usize, event: &Event) -> std::io::Result<()> {
+        let xy = |c: &Coord| format!("{},{}", c.x, c.y);
+        let row = match event {
+            Event::Born { pos, kind, organism } =>
+                format!("{},,,{},,{},,,", xy(pos), kind, organism),
+            Event::Died { pos, kind, cause } =>
+                format!("{},,,{},{},,,,", xy(pos), kind, cause.name()),
+            Event::Moved { organism, from, to } =>
+                format!("{},{},,,{},,,", xy(from), xy(to), organism),
+            Event::Mutated { organism, parent_genome, genome } =>
+                format!(",,,,,,{},{},{},", organism, genome, parent_genome),
+            Event::EnergyTransferred { from, to, amount } =>
+                format!("{},{},,,,,,{}", xy(from), xy(to), amount),
+        };
+        writeln!(self.out, "{},{},{}", step, event.name(), row)
+    }
+}
+
+impl Observer for EventLog {
+    fn on_event(&mut self, step: usize, event: &Event) {
+        if let Err(e) = self.write(step, event) {
+            panic!("cannot write the event log: {}", e);
+        }
+    }
+}
@@ -0,0 +1,269 @@
+use std::collections::{BTreeMap, HashSet};
+use std::str::FromStr;
+use serde::Serialize;
+
+use crate::cells::{Cell, CellKind};
+use crate::cli::QueryArgs;
+use crate::common::{Coord, ResourceType};
+use crate::map::Topology;
+use crate::simulation::Simulation;
+
+/// Kind asked for by `--kind`; producers can be picked by resource.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum KindFilter { Producer, Leaf, Root, Antenna, Conductor, Bud }
+
+impl FromStr for KindFilter {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, String> {
+        match s {
+            "producer" => Ok(Self::Producer),
+            "leaf" => Ok(Self::Leaf),
+            "root" => Ok(Self::Root),
+            "antenna" => Ok(Self::Antenna),
+            "conductor" => Ok(Self::Conductor),
+            "bud" => Ok(Self::Bud),
+            _ => Err(format!("unknown kind {:?} (expected producer, leaf, root, antenna, conductor or bud)", s)),
+        }
+    }
+}
+
+impl KindFilter {
+    fn matches(&self, kind: &CellKind) -> bool {
+        match (self, kind) {
+            (Self::Producer, CellKind::Producer(_)) => true,
+            (Self::Leaf, CellKind::Producer(p)) => matches!(p.resource, ResourceType::Solar),
+            (Self::Root, CellKind::Producer(p)) => matches!(p.resource, ResourceType::Organic),
+            (Self::Antenna, CellKind::Producer(p)) => matches!(p.resource, ResourceType::Electricity),
+            (Self::Conductor, CellKind::Conductor) => true,
+            (Self::Bud, CellKind::Storage(_)) => true,
+            _ => false,
+        }
+    }
+}
+
+impl QueryArgs {
+    fn matches(&self, cell: &Cell) -> bool {
+        let (x, y) = (cell.pos.x as usize, cell.pos.y as usize);
+        self.rect.is_none_or(|r| (r.x..r.x.saturating_add(r.width)).contains(&x) && (r.y..r.y.saturating_add(r.height)).contains(&y))
+            && self.kind.is_none_or(|k| k.matches(&cell.kind))
+            && self.min_energy.is_none_or(|e| cell.energy >= e)
+            && self.max_energy.is_none_or(|e| cell.energy <= e)
+            && self.min_life.is_none_or(|l| cell.life_time >= l)
+            && self.max_life.is_none_or(|l| cell.life_time <= l)
+    }
+}
+
+#[derive(Serialize)]
+struct LayerStats {
+    sum: f32,
+    mean: f32,
+    max: f32,
+}
+
+impl LayerStats {
+    fn of(values: &ndarray::Array2<f32>) -> Self {
+        Self { sum: values.sum(), mean: values.mean().unwrap_or(0.0), max: values.fold(0.0, |a, b| a.max(*b)) }
+    }
+}
+
+/// Summary of the matching cells and of the world they live in.
+#[derive(Serialize)]
+struct Summary {
+    width: usize,
+    height: usize,
+    topology: Topology,
+    iteration: usize,
+    seed: u64,
+    life_time: i16,
+    cells: usize,
+    leaves: usize,
+    roots: usize,
+    antennas: usize,
+    conductors: usize,
+    buds: usize,
+    organisms: usize,
+    genomes: usize,
+    total_energy: f64,
+    mean_energy: f64,
+    mean_life_time: f64,
+    /// Buds per controller architecture.
+    architectures: BTreeMap<String, usize>,
+    organics: LayerStats,
+    electric: LayerStats,
+    mean_light: f32,
+}
+
+#[derive(Serialize)]
+struct Tensor {
+    name: String,
+    shape: Vec<usize>,
+    /// L2 norm.
+    norm: f32,
+}
+
+#[derive(Serialize)]
+struct Genome {
+    architecture: String,
+    memory: usize,
+    parameters: usize,
+    tensors: Vec<Tensor>,
+}
+
+#[derive(Serialize)]
+struct CellInfo {
+    x: i64,
+    y: i64,
+    kind: &'static str,
+    energy: f32,
+    life_time: i16,
+    organism: String,
+    genome_id: String,
+    /// Only buds carry a genome.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    genome: Option<Genome>,
+}
+
+impl CellInfo {
+    fn of(cell: &Cell, with_genome: bool) -> Self {
+        let genome = match &cell.kind {
+            CellKind::Storage(st) if with_genome => {
+                let tensors: Vec<Tensor> = st.genome.parameters().into_iter().map(|(name, p)| Tensor {
+                    name,
+                    shape: p.shape().to_vec(),
+                    norm: p.iter().map(|v| v * v).sum::<f32>().sqrt(),
+                }).collect();
+                Some(Genome {
+                    architecture: st.genome.describe(),
+                    memory: st.genome.state_size(),
+                    parameters: tensors.iter().map(|t| t.shape.iter().product::<usize>()).sum(),
+                    tensors,
+                })
+            },
+            _ => None,
+        };
+        Self {
+            x: cell.pos.x,
+            y: cell.pos.y,
+            kind: cell.kind.name(),
+            energy: cell.energy,
+            life_time: cell.life_time,
+            organism: cell.organism.to_string(),
+            genome_id: cell.genome_id.to_string(),
+            genome,
+        }
+    }
+}
+
+#[derive(Serialize)]
+struct Report {
+    summary: Summary,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    cells: Option<Vec<CellInfo>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    cell: Option<CellInfo>,
+}
+
+fn summarize(simulation: &Simulation, cells: &[&Cell]) -> Summary {
+    let map = simulation.world_map();
+    let settings = simulation.settings();
+    let count = |f: &dyn Fn(&CellKind) -> bool| cells.iter().filter(|c| f(&c.kind)).count();
+    let total_energy: f64 = cells.iter().map(|c| c.energy as f64).sum();
+    let mut architectures = BTreeMap::new();
+    for cell in cells {
+        if let CellKind::Storage(st) = &cell.kind {
+            *architectures.entry(st.genome.describe()).or_insert(0) += 1;
+        }
+    }
+    let n = cells.len().max(1) as f64;
+    Summary {
+        width: map.width,
+        height: map.height,
+        topology: map.topology,
+        iteration: simulation.save_iter,
+        seed: settings.seed,
+        life_time: settings.life_time,
+        cells: cells.len(),
+        leaves: count(&|k| KindFilter::Leaf.matches(k)),
+        roots: count(&|k| KindFilter::Root.matches(k)),
+        antennas: count(&|k| KindFilter::Antenna.matches(k)),
+        conductors: count(&|k| KindFilter::Conductor.matches(k)),
+        buds: count(&|k| KindFilter::Bud.matches(k)),
+        organisms: cells.iter().map(|c| c.organism).collect::<HashSet<_>>().len(),
+        genomes: cells.iter().map(|c| c.genome_id).collect::<HashSet<_>>().len(),
+        total_energy,
+        mean_energy: total_energy / n,
+        mean_life_time: cells.iter().map(|c| c.life_time as f64).sum::<f64>() / n,
+        architectures,
+        organics: LayerStats::of(&map.organics),
+        electric: LayerStats::of(&map.electric),
+        mean_light: map.light.mean().unwrap_or(0.0),
+    }
+}
+
+fn print_cell(info: &CellInfo) {
+    println!("{},{}  {:<9} energy {:<10.4} life {:<4} organism {} genome {}",
+             info.x, info.y, info.kind, info.energy, info.life_time, info.organism, info.genome_id);
+    if let Some(g) = &info.genome {
+        println!("  genome:     {} (memory {}, {} parameters)", g.architecture, g.memory, g.parameters);
+        for t in &g.tensors {
+            println!("    {:<16} {:<12} norm {:.4}", t.name, format!("{:?}", t.shape), t.norm);
+        }
+    }
+}
+
+/// Print what `query` asks about a saved state: the summary of the matching cells,
+/// their list with `--cells`, one cell in full with `--cell`.
+pub fn inspect(simulation: &Simulation, query: &QueryArgs) -> Result<(), String> {
+    let cells: Vec<&Cell> = simulation.cells().filter(|c| query.matches(c)).collect();
+    let cell = match query.cell {
+        Some((x, y)) => match simulation.cell(&Coord { x, y }) {
+            Some(cell) => Some(CellInfo::of(cell, true)),
+            None => return Err(format!("there is no cell at {},{}", x, y)),
+        },
+        None => None,
+    };
+    let report = Report {
+        summary: summarize(simulation, &cells),
+        cells: query.cells.then(|| cells.iter().map(|c| CellInfo::of(c, false)).collect()),
+        cell,
+    };
+    if query.json {
+        println!("{}", serde_json::to_string_pretty(&report).map_err(|e| e.to_string())?);
+        return Ok(());
+    }
+
+    let s = &report.summary;
+    println!("map:          {}x{} (w x h), {:?}", s.width, s.height, s.topology);
+    println!("iteration:    {}", s.iteration);
+    println!("seed:         {}", s.seed);
+    println!("life time:    {}", s.life_time);
+    println!("cells:        {}", s.cells);
+    println!("  leaves:     {}", s.leaves);
+    println!("  roots:      {}", s.roots);
+    println!("  antennas:   {}", s.antennas);
+    println!("  conductors: {}", s.conductors);
+    println!("  buds:       {}", s.buds);
+    println!("organisms:    {}", s.organisms);
+    println!("genomes:      {}", s.genomes);
+    println!("total energy: {}", s.total_energy);
+    println!("mean energy:  {}", s.mean_energy);
+    println!("mean life:    {}", s.mean_life_time);
+    for (arch, n) in &s.architectures {
+        println!("genome:       {} ({} buds)", arch, n);
+    }
+    println!("organics:     sum {} mean {} max {}", s.organics.sum, s.organics.mean, s.organics.max);
+    println!("electric:     sum {} mean {} max {}", s.electric.sum, s.electric.mean, s.electric.max);
+    println!("mean light:   {}", s.mean_light);
+    if let Some(cells) = &report.cells {
+        println!();
+        for info in cells {
+            print_cell(info);
+        }
+    }
+    if let Some(info) = &report.cell {
+        println!();
+        print_cell(info);
+    }
+    Ok(())
+}
@@ -0,0 +1,54 @@
+use std::error::Error;
+use std::path::Path;
+use uuid::Uuid;
+
+use crate::common::append_csv;
+
+/// File the births are appended to, next to the state snapshot.
+pub const LINEAGE_FILE: &str = "lineage.csv";
+
+/// Birth of a bud. Every bud founds an organism: itself and the conductors and producers
+/// it leaves behind. The genome is the parent's unless it mutated.
+#[derive(Clone, Debug)]
+pub struct LineageRecord {
+    pub step: usize,
+    pub organism: Uuid,
+    /// `None` for the buds a world starts with.
+    pub parent_organism: Option<Uuid>,
+    pub genome: Uuid,
+    pub parent_genome: Option<Uuid>,
+    pub mutated: bool,
+}
+
+/// Births since the last flush.
+#[derive(Default)]
+pub struct LineageLog {
+    /// Step the births happen at.
+    pub step: usize,
+    records: Vec<LineageRecord>,
+}
+
+impl LineageLog {
+    /// Record a bud founding `organism` with `genome`; `parent` is the organism and genome
+    /// of the bud it split from.
+    pub fn birth(&mut self, organism: Uuid, genome: Uuid, parent: Option<(Uuid, Uuid)>) {
+        self.records.push(LineageRecord {
+            step: self.step,
+            organism,
+            parent_organism: parent.map(|p| p.0),
+            genome,
+            parent_genome: parent.map(|p| p.1),
+            mutated: parent.is_some_and(|p| p.1 != genome),
+        });
+    }
+
+    /// Append the pending births to the CSV at `path` and forget them.
+    pub fn flush(&mut self, path: &Path) -> Result<(), Box<dyn Error>> {
+        let id = |id: Option<Uuid>| id.map_or(String::new(), |id| id.to_string());
+        let rows = self.records.iter().map(|r| format!("{},{},{},{},{},{}", r.step, r.organism,
+                                                       id(r.parent_organism), r.genome, id(r.parent_genome), r.mutated));
+        append_csv(path, "step,organism,parent_organism,genome,parent_genome,mutated", rows)?;
+        self.records.clear();
+        Ok(())
+    }
+}
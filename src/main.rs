@@ -1,39 +1,56 @@
-use crate::common::Coord;
+use crate::common::{Coord, new_id, stream_rng, STREAM_GENERATION};
 use crate::map::{Map};
 use crate::simulation::{Simulation};
 use crate::cells::*;
+use crate::controller::random_controller;
+use crate::cli::{Cli, Command, RenderArgs, RunArgs, WorldArgs};
+use crate::config::{Config, SimulationSettings};
+use crate::events::{DeathCause, Event, EventLog};
+use crate::render::{Palette, Renderer};
+use crate::animation::{Animation, Recorder};
+use crate::trajectory::{convert_csv, Trajectory, TRAJECTORY_EXT};
+use crate::tui::Viewer;
+use crate::inspect::inspect;
+use crate::common::ensure_dir;
 
-use std::path::Path;
 use rand::{rng, Rng};
 use pbr::ProgressBar;
-use std::collections::HashSet;
+use std::collections::{HashMap, HashSet};
+use clap::Parser;
 
 use rayon::prelude::*;
 
 pub mod map;
 pub mod cells;
+pub mod controller;
 pub mod common;
 pub mod simulation;
+pub mod cli;
+pub mod config;
+pub mod snapshot;
+pub mod store;
+pub mod lineage;
+pub mod events;
+pub mod stats;
+pub mod render;
+pub mod animation;
+pub mod trajectory;
+pub mod tui;
+pub mod inspect;
 
 
-const N_RUNS: u64 = 3000;
-const SAVE_INTERVAL: u64 = N_RUNS / 10;
-
-const DEFAULT_N_CELLS: usize = 5000;
-const DEFAULT_MAP_H: usize = 1024;
-const DEFAULT_MAP_W: usize = 1024;
-const DEFAULT_LIFETIME: i16 = 150;
-
-
-fn generate_cells_parallel(h: usize, w: usize, n: usize) -> Vec<Cell> {
-    // generate candidates in parallel
-    let mut candidates: Vec<Cell> = (0..n).into_par_iter().map_init(
-        || rng(),
-        |local_rng, _i| {
-            let genome = Genome::random(1 + 2 * 5 * 5, 128, 256, 4 * 4, 0.0, 0.1);
+fn generate_cells_parallel(h: usize, w: usize, n: usize, settings: &SimulationSettings) -> Vec<Cell> {
+    let (seed, g) = (settings.seed, &settings.genome);
+    // generate candidates in parallel, each from its own stream so thread scheduling doesn't matter
+    let mut candidates: Vec<Cell> = (0..n).into_par_iter().map(
+        |i| {
+            let local_rng = &mut stream_rng(seed, STREAM_GENERATION, i as u64);
+            let genome = random_controller(local_rng, g, settings.sensors.input_size(), 4 * 4);
             Cell {
-                kind: CellKind::Storage(Storage { genome }),
-                life_time: DEFAULT_LIFETIME,
+                kind: CellKind::Storage(Storage::new(genome)),
+                organism: new_id(local_rng),
+                genome_id: new_id(local_rng),
+                life_time: settings.life_time,
                 pos: Coord {
                     x: local_rng.random_range(0..w) as i64,
                     y: local_rng.random_range(0..h) as i64,
@@ -59,40 +76,219 @@ fn generate_cells_parallel(h: usize, w: usize, n: usize) -> Vec<Cell> {
 }
 
 
-fn create_new_simulation() -> Simulation {
-    let world_map = Map::new(DEFAULT_MAP_W, DEFAULT_MAP_H);
+fn load_config(world: &WorldArgs) -> Config {
+    let mut config = match &world.config {
+        Some(path) => Config::load(path).unwrap_or_else(|e| {
+            panic!("cannot load config {:?}: {}", path, e);
+        }),
+        None => Config::default(),
+    };
+    if let Some(width) = world.width { config.world.width = width; }
+    if let Some(height) = world.height { config.world.height = height; }
+    if let Some(n_cells) = world.n_cells { config.world.n_cells = n_cells; }
+    if let Some(topology) = world.topology { config.world.topology = topology; }
+    if let Some(lifetime) = world.lifetime { config.simulation.life_time = lifetime; }
+    // a seed from the file is kept unless overridden; without either the run gets a fresh one
+    config.simulation.seed = world.seed
+        .or(config.seeded.then_some(config.simulation.seed))
+        .unwrap_or_else(|| rng().random());
+    if let Err(e) = config.validate() {
+        panic!("{}", e);
+    }
+    config
+}
+
+
+fn create_new_simulation(world: &WorldArgs) -> Simulation {
+    let config = load_config(world);
+    let w = &config.world;
+    println!("seed: {}", config.simulation.seed);
+    let world_map = Map::new(w.width, w.height).with_topology(w.topology);
+    println!("world generation...");
+    let cells = generate_cells_parallel(w.height, w.width, w.n_cells, &config.simulation);
     let mut s = Simulation::new(Some(world_map), 
                                                     String::from("saves"), 
                                                     String::from("snap"),
-                                                    DEFAULT_LIFETIME);
-    println!("world generation...");
-    s.add_cells(generate_cells_parallel(DEFAULT_MAP_H, DEFAULT_MAP_W, DEFAULT_N_CELLS));
+                                                    config.simulation);
+    s.add_founders(cells);
     s
 }
 
 
-fn main() {
-    let mut simulation = Simulation::load(Path::new("./saves_back")).unwrap_or_else(|_| {
-        println!("cannot load simulation");
-        create_new_simulation()
-    });
-    
+fn run(mut simulation: Simulation, args: &RunArgs) {
+    simulation.set_output(args.out_dir.clone(), args.snap_name.clone());
+    let save_interval = args.save_interval();
+    if let Some(path) = &args.events {
+        let log = EventLog::create(path).unwrap_or_else(|e| {
+            panic!("cannot create the event log {:?}: {}", path, e);
+        });
+        simulation.subscribe(log);
+    }
+    let map = simulation.world_map();
+    let mut recorder = recorder(&args.render, map.width, map.height);
+
     println!("\nrunning the world!");
-    let mut pb = ProgressBar::new(N_RUNS);
-    for i in 0..N_RUNS {
+    let mut viewer = args.tui.then(|| {
+        Viewer::start(palette(&args.render)).unwrap_or_else(|e| panic!("cannot start the viewer: {}", e))
+    });
+    let mut pb = (!args.tui).then(|| ProgressBar::new(args.steps));
+    let mut deaths: HashMap<DeathCause, usize> = HashMap::new();
+    for i in 0..args.steps {
         simulation.step();
+        for event in simulation.events() {
+            if let Event::Died { cause, .. } = event {
+                *deaths.entry(*cause).or_default() += 1;
+            }
+        }
         if simulation.save_view(false).is_err() {
             println!("We broke around the saving of the view to file!");
             panic!("save error!");
         }
-        if i > 0 && i % SAVE_INTERVAL == 0 && simulation.save_state(true).is_err() {
+        if let Some(recorder) = &mut recorder {
+            let (step, cells) = (simulation.save_iter, simulation.cells_count());
+            if let Err(e) = recorder.record(step, cells, |r| r.render(&simulation)) {
+                panic!("cannot draw step {}: {}", step, e);
+            }
+        }
+        let quit = match &mut viewer {
+            Some(viewer) => !viewer.frame(&simulation).unwrap_or_else(|e| panic!("viewer error: {}", e)),
+            None => false,
+        };
+        // counted before saving, so a resumed run picks up at the next step
+        simulation.save_iter += 1;
+        if i > 0 && i % save_interval == 0 && simulation.save_state(true).is_err() {
             println!("We broke around the saving of the state to file!");
             panic!("save error!");
         }
-        simulation.save_iter += 1;
+        if let Some(pb) = &mut pb {
+            pb.inc();
+        }
+        // the step shown last is kept, the state is saved below
+        if quit {
+            break;
+        }
+    }
+    // the last steps are kept even if they fell between two periodic saves
+    if let Err(e) = simulation.save_state(true) {
+        panic!("cannot save the final state: {}", e);
+    }
+    // gives the terminal back before the summary
+    drop(viewer);
+    if let Some(mut pb) = pb {
+        pb.finish_println("done");
+    }
+    if let Some(recorder) = recorder {
+        recorder.finish().unwrap_or_else(|e| panic!("cannot finish the animation: {}", e));
+    }
+    println!("\ndeaths:");
+    for cause in DeathCause::ALL {
+        println!("  {:<11} {}", format!("{}:", cause.name()), deaths.get(&cause).unwrap_or(&0));
+    }
+}
+
+
+fn palette(args: &RenderArgs) -> Palette {
+    match &args.palette {
+        Some(path) => Palette::load(path).unwrap_or_else(|e| {
+            panic!("cannot load palette {:?}: {}", path, e);
+        }),
+        None => Palette::default(),
+    }
+}
+
+
+/// Outputs asked for by the render flags for a `width` x `height` map; `None` if nothing
+/// is to be drawn.
+fn recorder(args: &RenderArgs, width: usize, height: usize) -> Option<Recorder> {
+    if args.png_dir.is_none() && args.animation.is_none() {
+        return None;
+    }
+    if let Some(Err(e)) = args.crop.map(|c| c.check(width, height)) {
+        panic!("{}", e);
+    }
+    if let Some(dir) = &args.png_dir {
+        ensure_dir(dir).expect("cannot create the PNG directory");
+    }
+    let animation = args.animation.as_ref().map(|path| {
+        Animation::new(path, args.fps).unwrap_or_else(|e| panic!("{}", e))
+    });
+    Some(Recorder {
+        renderer: Renderer { palette: palette(args), scale: args.png_scale, overlays: args.overlay.clone(), crop: args.crop },
+        png_dir: args.png_dir.clone(),
+        png_every: args.png_every,
+        animation,
+        animation_every: args.animation_every,
+        caption: args.caption,
+    })
+}
+
+
+/// Draw the trajectory of a finished run; it keeps no pollution, so there are no overlays.
+fn animate(dir: &std::path::Path, name: &str, args: &RenderArgs) {
+    let path = dir.join(format!("{}.{}", name, TRAJECTORY_EXT));
+    let mut trajectory = Trajectory::open(&path).unwrap_or_else(|e| {
+        panic!("cannot read trajectory {:?}: {}", path, e);
+    });
+    let steps = trajectory.steps();
+    let (width, height) = (trajectory.width, trajectory.height);
+    let Some(mut recorder) = recorder(args, width, height) else {
+        panic!("nothing to draw: pass --png-dir or --animation");
+    };
+    let mut pb = ProgressBar::new(steps.len() as u64);
+    for step in steps {
+        let cells = trajectory.cells(step).unwrap_or_else(|e| {
+            panic!("cannot read step {} of {:?}: {}", step, path, e);
+        });
+        if let Err(e) = recorder.record(step, cells.len(), |r| r.render_view(width, height, &cells)) {
+            panic!("cannot draw step {}: {}", step, e);
+        }
         pb.inc();
     }
     pb.finish_println("done");
+    recorder.finish().unwrap_or_else(|e| panic!("cannot finish the animation: {}", e));
+}
+
 
-    println!("Hello, world!");
+fn main() {
+    let cli = Cli::parse();
+    match cli.command {
+        Command::New { world, run: run_args } => {
+            run(create_new_simulation(&world), &run_args);
+        },
+        Command::Resume { dir, run: run_args } => {
+            let simulation = Simulation::load(&dir).unwrap_or_else(|e| {
+                panic!("cannot load simulation from {:?}: {}", dir, e);
+            });
+            run(simulation, &run_args);
+        },
+        Command::Run { from, world, run: run_args } => {
+            // only a missing save starts a new world; a broken one is not overwritten
+            let simulation = if Simulation::save_exists(&from) {
+                Simulation::load(&from).unwrap_or_else(|e| {
+                    panic!("cannot load simulation from {:?}: {}", from, e);
+                })
+            } else {
+                println!("no saved state in {:?}", from);
+                create_new_simulation(&world)
+            };
+            run(simulation, &run_args);
+        },
+        Command::Inspect { dir, query } => {
+            let simulation = Simulation::load(&dir).unwrap_or_else(|e| {
+                panic!("cannot load simulation from {:?}: {}", dir, e);
+            });
+            if let Err(e) = inspect(&simulation, &query) {
+                panic!("{}", e);
+            }
+        },
+        Command::Animate { dir, snap_name, render } => {
+            animate(&dir, &snap_name, &render);
+        },
+        Command::Convert { dir, snap_name } => {
+            let frames = convert_csv(&dir, &snap_name).unwrap_or_else(|e| {
+                panic!("cannot convert views in {:?}: {}", dir, e);
+            });
+            println!("{} frames written to {:?}", frames, dir.join(format!("{}.{}", snap_name, TRAJECTORY_EXT)));
+        },
+    }
 }
@@ -1,59 +1,209 @@
-use ndarray::{Array2};
-use std::fs::{OpenOptions, File};
-use std::io::{BufWriter, Read, Write};
+use ndarray::{Array2, Zip};
+use rayon::prelude::*;
+use std::fs::File;
+use std::io::Read;
 use std::path::Path;
+use std::str::FromStr;
 use std::error::Error;
 use crate::common::*;
+use crate::config::{LightSettings, PollutionSettings};
+use serde::{Deserialize, Serialize};
 
+/// How windows reaching past the map border are filled.
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum Padding {
+    /// Fixed value, e.g. -1 so buds can tell the border from clean ground.
+    Constant(f32),
+    /// Repeat the nearest border cell.
+    Edge,
+    /// Take the cell from the opposite side of the map.
+    Wrap,
+}
+
+/// Shape of the world: what lies past the map border.
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Default)]
+#[serde(rename_all = "snake_case")]
+pub enum Topology {
+    /// Nothing; coordinates past the border are invalid.
+    #[default]
+    Bounded,
+    /// The opposite side of the map; both axes wrap around.
+    Torus,
+}
+
+impl FromStr for Topology {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, String> {
+        match s {
+            "bounded" => Ok(Self::Bounded),
+            "torus" => Ok(Self::Torus),
+            _ => Err(format!("unknown topology {:?} (expected bounded or torus)", s)),
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize)]
 pub struct Map {
     pub width: usize,
     pub height: usize,
+    pub topology: Topology,
     pub organics: Array2<f32>,
     pub electric: Array2<f32>,
+    /// Sunlight of the current step, in `[0, 1]`.
+    pub light: Array2<f32>,
 }
 
 impl Map {
     pub fn new(width: usize, height: usize) -> Self {
         let organics = Array2::zeros((height, width));
         let electric = Array2::zeros((height, width));
-        Self { width, height, organics, electric }
+        let light = Array2::ones((height, width));
+        Self { width, height, topology: Topology::Bounded, organics, electric, light }
+    }
+
+    pub fn with_topology(mut self, topology: Topology) -> Self {
+        self.topology = topology;
+        self
     }
 
     pub fn in_bounds(&self, x: i64, y: i64) -> bool {
         x >= 0 && x < self.width as i64 && y >= 0 && y < self.height as i64
     }
 
+    /// Map cell `(x, y)` refers to under the map topology, `None` if there is none.
+    pub fn wrap(&self, x: i64, y: i64) -> Option<Coord> {
+        match self.topology {
+            Topology::Bounded => self.in_bounds(x, y).then_some(Coord { x, y }),
+            Topology::Torus => Some(Coord {
+                x: x.rem_euclid(self.width as i64),
+                y: y.rem_euclid(self.height as i64),
+            }),
+        }
+    }
+
+    /// Cell next to `coord` in direction `dir`.
+    pub fn neighbour(&self, coord: &Coord, dir: &Direction) -> Option<Coord> {
+        let c = coord.shift(dir);
+        self.wrap(c.x, c.y)
+    }
+
+    /// `(y, x)` indices of the square of side `2 * r + 1` around `coord`, clipped or
+    /// wrapped at the border; every cell appears once even on tiny torus maps.
+    pub fn area(&self, coord: &Coord, r: i64) -> Vec<(usize, usize)> {
+        let mut idx = Vec::with_capacity(((2 * r + 1) * (2 * r + 1)) as usize);
+        for dy in -r..=r {
+            for dx in -r..=r {
+                if let Some(c) = self.wrap(coord.x + dx, coord.y + dy) {
+                    let i = (c.y as usize, c.x as usize);
+                    if !idx.contains(&i) {
+                        idx.push(i);
+                    }
+                }
+            }
+        }
+        idx
+    }
+
     pub fn is_lvl_critical(&self, x: usize, y: usize, critival_lvl: f32) -> (bool, bool) {
         (self.organics[(y,x)] > critival_lvl, self.electric[(y,x)] > critival_lvl)
     }
 
-    pub fn set_organics(&mut self, x: usize, y: usize, val: f32) {
-        self.organics[(y,x)] = val;
-    }
-    pub fn set_electric(&mut self, x: usize, y: usize, val: f32) {
-        self.electric[(y,x)] = val;
+    /// Unshaded sunlight at `step`: the day/night cycle times a latitude falloff from
+    /// an equator that wanders north and south with the seasons.
+    pub fn update_light(&mut self, step: usize, settings: &LightSettings) {
+        use std::f32::consts::TAU;
+        let phase = |period: usize| if period == 0 { 0.0 } else { (step % period) as f32 / period as f32 };
+
+        let day = if settings.day_length == 0 { 1.0 } else { (TAU * phase(settings.day_length)).sin().max(0.0) };
+        let day = settings.night + (1.0 - settings.night) * day;
+        let equator = settings.tilt * (TAU * phase(settings.year_length)).sin();
+
+        let h = self.height as f32;
+        for (y, mut row) in self.light.rows_mut().into_iter().enumerate() {
+            // -1 at the top row, 1 at the bottom one
+            let lat = 2.0 * (y as f32 + 0.5) / h - 1.0;
+            let falloff = (1.0 - settings.latitude * (lat - equator).abs()).clamp(0.0, 1.0);
+            row.fill(day * falloff);
+        }
     }
 
-    pub fn save(&self, save_path: &Path, overwrite: bool) -> Result<(), Box<dyn Error>> {
-        let meta_path = save_path.join("meta.txt");
-        if !meta_path.as_path().exists() || overwrite {
-            let f = OpenOptions::new()
-                .create(true)
-                .write(true)
-                .truncate(true)
-                .open(meta_path.as_path())?;
-            let mut w = BufWriter::new(f);
-            writeln!(w, "{},{}", self.height, self.width)?;
-            w.flush()?;
+    /// One step of the pollution field on both layers: decay, diffusion and wind, then
+    /// decomposition of organics into the electric layer.
+    pub fn update_pollution(&mut self, settings: &PollutionSettings) {
+        let still = settings.wind == [0.0, 0.0];
+        if settings.decrease > 0.0 || settings.diffusion > 0.0 {
+            self.organics = self.diffuse(&self.organics, settings);
+            self.electric = self.diffuse(&self.electric, settings);
         }
+        if !still {
+            self.organics = self.advect(&self.organics, settings.wind);
+            self.electric = self.advect(&self.electric, settings.wind);
+        }
+        if settings.decomposition > 0.0 {
+            let rate = settings.decomposition;
+            Zip::from(&mut self.organics).and(&mut self.electric).for_each(|o, e| {
+                let rotten = *o * rate;
+                *o -= rotten;
+                *e += rotten;
+            });
+        }
+    }
+
+    /// Value of `layer` at `(x, y)` under the map topology.
+    fn sample(&self, layer: &Array2<f32>, x: i64, y: i64) -> Option<f32> {
+        self.wrap(x, y).map(|c| layer[(c.y as usize, c.x as usize)])
+    }
 
-        let npy_path = save_path.join("organic.npy");
-        save_npy(&self.organics, npy_path.as_path())?;
+    /// New layer computed row by row in parallel; every cell depends on the old layer only.
+    fn map_rows(&self, f: impl Fn(i64, i64) -> f32 + Sync) -> Array2<f32> {
+        let mut data = vec![0f32; self.width * self.height];
+        data.par_chunks_mut(self.width).enumerate().for_each(|(y, row)| {
+            for (x, v) in row.iter_mut().enumerate() {
+                *v = f(x as i64, y as i64);
+            }
+        });
+        Array2::from_shape_vec((self.height, self.width), data).unwrap()
+    }
+
+    /// Explicit 5-point diffusion followed by exponential decay. The border of a bounded
+    /// map reflects, so diffusion alone never loses pollution.
+    fn diffuse(&self, layer: &Array2<f32>, settings: &PollutionSettings) -> Array2<f32> {
+        let d = settings.diffusion;
+        let keep = 1.0 - settings.decrease;
+        self.map_rows(|x, y| {
+            let v = layer[(y as usize, x as usize)];
+            let around: f32 = Direction::all_directions().iter()
+                .map(|dir| {
+                    let c = Coord { x, y }.shift(dir);
+                    self.sample(layer, c.x, c.y).unwrap_or(v)
+                })
+                .sum();
+            keep * ((1.0 - d) * v + d / 4.0 * around)
+        })
+    }
 
-        let npy_path = save_path.join("electric.npy");
-        save_npy(&self.electric, npy_path.as_path())?;
+    /// Semi-lagrangian transport: every cell takes the bilinearly interpolated value from
+    /// `wind` cells upwind. Clean air blows in over the border of a bounded map.
+    fn advect(&self, layer: &Array2<f32>, wind: [f32; 2]) -> Array2<f32> {
+        self.map_rows(|x, y| {
+            let sx = x as f32 - wind[0];
+            let sy = y as f32 - wind[1];
+            let (x0, y0) = (sx.floor(), sy.floor());
+            let (fx, fy) = (sx - x0, sy - y0);
+            let (x0, y0) = (x0 as i64, y0 as i64);
+            let at = |dx: i64, dy: i64| self.sample(layer, x0 + dx, y0 + dy).unwrap_or(0.0);
+            (1.0 - fy) * ((1.0 - fx) * at(0, 0) + fx * at(1, 0))
+                + fy * ((1.0 - fx) * at(0, 1) + fx * at(1, 1))
+        })
+    }
 
-        Ok(())
+    pub fn set_organics(&mut self, x: usize, y: usize, val: f32) {
+        self.organics[(y,x)] = val;
+    }
+    pub fn set_electric(&mut self, x: usize, y: usize, val: f32) {
+        self.electric[(y,x)] = val;
     }
 
     pub fn load(save_path: &Path) -> Result<Self, Box<dyn Error>> {
@@ -81,12 +231,15 @@ impl Map {
 
         let organics: Array2<f32> = load_npy(&organic_path)?;
         let electric: Array2<f32> = load_npy(&electric_path)?;
+        let light = Array2::ones((height, width));
 
         Ok(Map {
             height,
             width,
+            topology: Topology::Bounded,
             organics,
             electric,
+            light,
         })
     }
 }
\ No newline at end of file
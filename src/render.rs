@@ -0,0 +1,289 @@
+use std::error::Error;
+use std::fs::{self, File};
+use std::io::BufWriter;
+use std::path::Path;
+use std::str::FromStr;
+use ndarray::s;
+use serde::{Deserialize, Serialize};
+
+use crate::cells::CellKind;
+use crate::map::Map;
+use crate::simulation::Simulation;
+
+pub type Rgb = [u8; 3];
+
+/// Cell of a view written by `Simulation::save_view`: `x`, `y` and `CellKind::str`.
+pub type ViewCell = (i64, i64, String);
+
+/// Pollution layer drawn under the cells.
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum Layer { Organics, Electric }
+
+impl FromStr for Layer {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, String> {
+        match s {
+            "organics" => Ok(Self::Organics),
+            "electric" => Ok(Self::Electric),
+            _ => Err(format!("unknown layer {:?} (expected organics or electric)", s)),
+        }
+    }
+}
+
+/// Colours of the picture; a palette file may set any of them.
+#[derive(Serialize, Deserialize, Clone, Debug)]
+#[serde(default, deny_unknown_fields)]
+pub struct Palette {
+    pub background: Rgb,
+    /// Producers by `ResourceType`; fields are named after `CellKind::name`.
+    pub leaf: Rgb,
+    pub root: Rgb,
+    pub antenna: Rgb,
+    pub conductor: Rgb,
+    pub bud: Rgb,
+    /// Colour of a layer at the critical level; lower levels fade into the background.
+    pub organics: Rgb,
+    pub electric: Rgb,
+}
+
+impl Default for Palette {
+    fn default() -> Self {
+        Self {
+            background: [16, 16, 16],
+            leaf: [80, 200, 60],
+            root: [150, 100, 50],
+            antenna: [80, 140, 230],
+            conductor: [200, 200, 170],
+            bud: [230, 60, 60],
+            organics: [170, 110, 40],
+            electric: [40, 110, 220],
+        }
+    }
+}
+
+impl Palette {
+    /// Read a palette from a TOML file.
+    pub fn load(path: &Path) -> Result<Self, Box<dyn Error>> {
+        Ok(toml::from_str(&fs::read_to_string(path)?)?)
+    }
+
+    pub fn cell(&self, kind: &CellKind) -> Rgb {
+        self.named(kind.name())
+    }
+
+    /// Colour of a kind by `CellKind::name`; anything else, such as a producer of a
+    /// view, is drawn as a leaf.
+    fn named(&self, name: &str) -> Rgb {
+        match name {
+            "root" => self.root,
+            "antenna" => self.antenna,
+            "conductor" => self.conductor,
+            "bud" => self.bud,
+            _ => self.leaf,
+        }
+    }
+}
+
+/// RGB picture, row by row.
+pub struct Frame {
+    pub width: usize,
+    pub height: usize,
+    pub rgb: Vec<u8>,
+}
+
+impl Frame {
+    pub fn write_png(&self, path: &Path) -> Result<(), Box<dyn Error>> {
+        let w = BufWriter::new(File::create(path)?);
+        let mut encoder = png::Encoder::new(w, self.width as u32, self.height as u32);
+        encoder.set_color(png::ColorType::Rgb);
+        encoder.set_depth(png::BitDepth::Eight);
+        let mut writer = encoder.write_header()?;
+        writer.write_image_data(&self.rgb)?;
+        writer.finish()?;
+        Ok(())
+    }
+
+    /// Write `text` in the top left corner, white on a dark box. The font has digits,
+    /// capital letters, space and a few signs; anything else is left blank.
+    pub fn caption(&mut self, text: &str) {
+        // pixel size of a font dot, so the text stays readable on big frames
+        let dot = (self.width / 160).max(1);
+        let (cols, rows) = (text.chars().count() * (GLYPH_W + 1) + 1, GLYPH_H + 2);
+        for y in 0..(rows * dot).min(self.height) {
+            for x in 0..(cols * dot).min(self.width) {
+                let i = (y * self.width + x) * 3;
+                for c in &mut self.rgb[i..i + 3] {
+                    *c /= 4;
+                }
+            }
+        }
+        for (n, ch) in text.chars().enumerate() {
+            let glyph = glyph(ch.to_ascii_uppercase());
+            for (gy, bits) in glyph.iter().enumerate() {
+                for gx in 0..GLYPH_W {
+                    if bits >> (GLYPH_W - 1 - gx) & 1 == 0 { continue; }
+                    let (px, py) = ((1 + n * (GLYPH_W + 1) + gx) * dot, (1 + gy) * dot);
+                    for y in py..(py + dot).min(self.height) {
+                        for x in px..(px + dot).min(self.width) {
+                            let i = (y * self.width + x) * 3;
+                            self.rgb[i..i + 3].copy_from_slice(&[255, 255, 255]);
+                        }
+                    }
+                }
+            }
+        }
+    }
+}
+
+const GLYPH_W: usize = 3;
+const GLYPH_H: usize = 5;
+
+/// 3x5 bitmap of `ch`, one row per byte, the leftmost dot in the highest of the three bits.
+fn glyph(ch: char) -> [u8; GLYPH_H] {
+    match ch {
+        '0' => [0b111, 0b101, 0b101, 0b101, 0b111],
+        '1' => [0b010, 0b110, 0b010, 0b010, 0b111],
+        '2' => [0b111, 0b001, 0b111, 0b100, 0b111],
+        '3' => [0b111, 0b001, 0b111, 0b001, 0b111],
+        '4' => [0b101, 0b101, 0b111, 0b001, 0b001],
+        '5' => [0b111, 0b100, 0b111, 0b001, 0b111],
+        '6' => [0b111, 0b100, 0b111, 0b101, 0b111],
+        '7' => [0b111, 0b001, 0b010, 0b010, 0b010],
+        '8' => [0b111, 0b101, 0b111, 0b101, 0b111],
+        '9' => [0b111, 0b101, 0b111, 0b001, 0b111],
+        'A' => [0b010, 0b101, 0b111, 0b101, 0b101],
+        'B' => [0b110, 0b101, 0b110, 0b101, 0b110],
+        'C' => [0b011, 0b100, 0b100, 0b100, 0b011],
+        'D' => [0b110, 0b101, 0b101, 0b101, 0b110],
+        'E' => [0b111, 0b100, 0b110, 0b100, 0b111],
+        'F' => [0b111, 0b100, 0b110, 0b100, 0b100],
+        'G' => [0b011, 0b100, 0b101, 0b101, 0b011],
+        'H' => [0b101, 0b101, 0b111, 0b101, 0b101],
+        'I' => [0b111, 0b010, 0b010, 0b010, 0b111],
+        'J' => [0b001, 0b001, 0b001, 0b101, 0b010],
+        'K' => [0b101, 0b101, 0b110, 0b101, 0b101],
+        'L' => [0b100, 0b100, 0b100, 0b100, 0b111],
+        'M' => [0b101, 0b111, 0b111, 0b101, 0b101],
+        'N' => [0b110, 0b101, 0b101, 0b101, 0b101],
+        'O' => [0b010, 0b101, 0b101, 0b101, 0b010],
+        'P' => [0b110, 0b101, 0b110, 0b100, 0b100],
+        'Q' => [0b010, 0b101, 0b101, 0b110, 0b011],
+        'R' => [0b110, 0b101, 0b110, 0b101, 0b101],
+        'S' => [0b011, 0b100, 0b010, 0b001, 0b110],
+        'T' => [0b111, 0b010, 0b010, 0b010, 0b010],
+        'U' => [0b101, 0b101, 0b101, 0b101, 0b111],
+        'V' => [0b101, 0b101, 0b101, 0b101, 0b010],
+        'W' => [0b101, 0b101, 0b111, 0b111, 0b101],
+        'X' => [0b101, 0b101, 0b010, 0b101, 0b101],
+        'Y' => [0b101, 0b101, 0b010, 0b010, 0b010],
+        'Z' => [0b111, 0b001, 0b010, 0b100, 0b111],
+        ':' => [0b000, 0b010, 0b000, 0b010, 0b000],
+        '.' => [0b000, 0b000, 0b000, 0b000, 0b010],
+        '-' => [0b000, 0b000, 0b111, 0b000, 0b000],
+        '/' => [0b001, 0b001, 0b010, 0b100, 0b100],
+        _ => [0; GLYPH_H],
+    }
+}
+
+/// Region of the map to draw, in map cells.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Crop { pub x: usize, pub y: usize, pub width: usize, pub height: usize }
+
+impl FromStr for Crop {
+    type Err = String;
+
+    /// `x,y,width,height`.
+    fn from_str(s: &str) -> Result<Self, String> {
+        let parts: Vec<usize> = s.split(',')
+            .map(|p| p.trim().parse::<usize>())
+            .collect::<Result<_, _>>()
+            .map_err(|e| format!("bad crop {:?}: {}", s, e))?;
+        match parts[..] {
+            [x, y, width, height] if width > 0 && height > 0 => Ok(Self { x, y, width, height }),
+            _ => Err(format!("bad crop {:?} (expected x,y,width,height)", s)),
+        }
+    }
+}
+
+impl Crop {
+    /// The crop may run past the right and bottom edges, where it is cut, but has to
+    /// start on the map.
+    pub fn check(&self, width: usize, height: usize) -> Result<(), String> {
+        if self.x >= width || self.y >= height {
+            return Err(format!("crop at {},{} is outside the {}x{} map", self.x, self.y, width, height));
+        }
+        Ok(())
+    }
+}
+
+/// Draws the world: one `scale` x `scale` square per map cell, the cells over the
+/// pollution `overlays`, only the `crop` region if there is one.
+pub struct Renderer {
+    pub palette: Palette,
+    pub scale: usize,
+    pub overlays: Vec<Layer>,
+    pub crop: Option<Crop>,
+}
+
+impl Renderer {
+    pub fn render(&self, simulation: &Simulation) -> Frame {
+        let map = simulation.world_map();
+        let critical = simulation.settings().pollution.critical_lvl;
+        let cells = simulation.cells().map(|c| (c.pos.x, c.pos.y, self.palette.cell(&c.kind)));
+        self.draw(map.width, map.height, Some((map, critical)), cells)
+    }
+
+    /// Draw a view written by `Simulation::save_view`; views have no pollution and
+    /// tell producers apart only from the rest.
+    pub fn render_view(&self, width: usize, height: usize, cells: &[ViewCell]) -> Frame {
+        let cells = cells.iter().map(|(x, y, kind)| (*x, *y, self.palette.named(kind)));
+        self.draw(width, height, None, cells)
+    }
+
+    fn draw(&self, width: usize, height: usize, layers: Option<(&Map, f32)>,
+            cells: impl Iterator<Item = (i64, i64, Rgb)>) -> Frame {
+        let crop = self.crop.unwrap_or(Crop { x: 0, y: 0, width, height });
+        // the crop is cut to the map
+        let (x0, y0) = (crop.x.min(width), crop.y.min(height));
+        let (w, h) = (crop.width.min(width - x0), crop.height.min(height - y0));
+        let mut colours = vec![self.palette.background; w * h];
+        if let Some((map, critical)) = layers {
+            for layer in &self.overlays {
+                let (values, over) = match layer {
+                    Layer::Organics => (&map.organics, self.palette.organics),
+                    Layer::Electric => (&map.electric, self.palette.electric),
+                };
+                let region = values.slice(s![y0..y0 + h, x0..x0 + w]);
+                for ((y, x), v) in region.indexed_iter() {
+                    let t = (v / critical).clamp(0.0, 1.0);
+                    for (c, o) in colours[y * w + x].iter_mut().zip(over) {
+                        *c = (*c as f32 + (o as f32 - *c as f32) * t) as u8;
+                    }
+                }
+            }
+        }
+        for (x, y, colour) in cells {
+            let (x, y) = (x as usize, y as usize);
+            if (x0..x0 + w).contains(&x) && (y0..y0 + h).contains(&y) {
+                colours[(y - y0) * w + x - x0] = colour;
+            }
+        }
+
+        let scale = self.scale.max(1);
+        let (width, height) = (w * scale, h * scale);
+        let mut rgb = Vec::with_capacity(width * height * 3);
+        for row in colours.chunks(w.max(1)) {
+            let line: Vec<u8> = row.iter()
+                .flat_map(|c| std::iter::repeat_n(c, scale))
+                .flatten()
+                .copied()
+                .collect();
+            for _ in 0..scale {
+                rgb.extend_from_slice(&line);
+            }
+        }
+        Frame { width, height, rgb }
+    }
+}
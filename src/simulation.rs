@@ -1,126 +1,34 @@
-use std::collections::{HashMap};
-use rand::{prelude::*, rng};
-use ndarray::{s, Array2, SliceInfo, Dim, SliceInfoElem};
-use rayon::iter::FromParallelIterator;
-use std::cmp;
-use std::fs::{OpenOptions, File};
-use std::io::{BufRead, BufReader, BufWriter, Write};
+use std::collections::{HashMap, HashSet, VecDeque};
+use rand::prelude::*;
+use rayon::prelude::*;
+use ndarray::{s, Array1, Array2};
+use std::fs::File;
+use std::io::{BufRead, BufReader};
 use std::path::Path;
 use std::error::Error;
+use std::sync::Arc;
 
 use crate::common::*;
-use crate::map::{Map};
+use crate::map::{Map, Padding, Topology};
 use crate::cells::*;
+use crate::config::SimulationSettings;
+use crate::controller::Controller;
+use crate::snapshot::{Snapshot, SnapshotRef, SNAPSHOT_FILE};
+use crate::store::CellStore;
+use crate::lineage::{LineageLog, LINEAGE_FILE};
+use crate::events::{DeathCause, Event, Observer};
+use crate::stats::{StatsLog, StepStats, STATS_FILE};
+use crate::trajectory::{TrajectoryWriter, TRAJECTORY_EXT};
 
 
-fn shuffled_indices(n: usize) -> Vec<usize> {
+fn shuffled_indices(n: usize, rng: &mut SimRng) -> Vec<usize> {
     let mut idx: Vec<usize> = (0..n).collect();
-    let mut rng = rand::rng();
-    idx.shuffle(&mut rng);
+    idx.shuffle(rng);
     idx
 }
 
-struct SimulationSettings {
-    life_time: i16,
-    energy_expanse: HashMap<String, f32>,
-    polution_increase: f32,
-    polution_decrease: f32,
-
-    polution_critical_lvl: f32,
-}
-
-impl SimulationSettings {
-    fn save(&self, path: &Path, overwrite: bool) -> std::io::Result<()> {
-        if !path.exists() || overwrite {
-            let f = OpenOptions::new()
-                .create(true)
-                .write(true)
-                .truncate(true)
-                .open(path)?;
-            let mut w = BufWriter::new(f);
-            // запись размеров: "width height"
-            writeln!(w, "{}", self.life_time)?;
-            writeln!(w, "{}", self.polution_increase)?;
-            writeln!(w, "{}", self.polution_decrease)?;
-            writeln!(w, "{}", self.polution_critical_lvl)?;
-            for (key, val) in &self.energy_expanse {
-                writeln!(w, "{}, {}", key, val)?;
-            }
-            w.flush()?;
-        }
-
-        Ok(())
-    }
-
-    fn load(path: &Path) -> Result<Self, Box<dyn Error>> {
-        use std::io::BufRead;
-        let f = File::open(path)?;
-        let mut reader = std::io::BufReader::new(f);
-        let mut line = String::new();
-
-        // life_time
-        line.clear();
-        if reader.read_line(&mut line)? == 0 {
-            return Err("simulation settings: missing life_time".into());
-        }
-        let life_time: i16 = line.trim().parse()?;
-
-        // polution_increase
-        line.clear();
-        if reader.read_line(&mut line)? == 0 {
-            return Err("simulation settings: missing polution_increase".into());
-        }
-        let polution_increase: f32 = line.trim().parse()?;
-
-        // polution_decrease
-        line.clear();
-        if reader.read_line(&mut line)? == 0 {
-            return Err("simulation settings: missing polution_decrease".into());
-        }
-        let polution_decrease: f32 = line.trim().parse()?;
-
-        // polution_decrease
-        line.clear();
-        if reader.read_line(&mut line)? == 0 {
-            return Err("simulation settings: missing polution_critical_lvl".into());
-        }
-        let polution_critical_lvl: f32 = line.trim().parse()?;
-
-        // remaining lines: energy_expanse key, val pairs
-        let mut energy_expanse: HashMap<String, f32> = HashMap::new();
-        loop {
-            line.clear();
-            let bytes = reader.read_line(&mut line)?;
-            if bytes == 0 {
-                break;
-            }
-            let s = line.trim();
-            if s.is_empty() {
-                continue;
-            }
-            // expect "key, val" or "key,val"
-            let parts: Vec<&str> = s.split(',').map(|p| p.trim()).collect();
-            if parts.len() != 2 {
-                return Err(format!("invalid energy_expanse line: {}", s).into());
-            }
-            let key = parts[0].to_string();
-            let val: f32 = parts[1].parse()?;
-            energy_expanse.insert(key, val);
-        }
-
-        Ok(SimulationSettings {
-            life_time,
-            energy_expanse,
-            polution_increase,
-            polution_decrease,
-            polution_critical_lvl,
-        })
-    }
-}
-
-
 pub struct Simulation {
-    cells: HashMap<(i64,i64), Cell>,
+    cells: CellStore,
     world_map: Map,
 
     
@@ -129,32 +37,24 @@ pub struct Simulation {
     save_file_name: String,
 
     settings: SimulationSettings,
+    rng: SimRng,
+    lineage: LineageLog,
+    stats: StatsLog,
+    /// What happened during the last step.
+    events: Vec<Event>,
+    observers: Vec<Box<dyn Observer>>,
+    /// Written by `save_view`, opened with the first view.
+    trajectory: Option<TrajectoryWriter>,
 }
 
 impl Simulation {
-    fn get_energy_expanse() -> HashMap<String, f32> {
-        let x = HashMap::<String, f32>::from_par_iter([
-            (String::from("producer"), 0.1),
-            (String::from("storage"), 0.25),
-        ]);
-        return x;
-    }
-
     pub fn new(world_map: Option<Map>, 
                save_path: String, save_file_name: String,
-               life_time: i16) -> Self {
+               settings: SimulationSettings) -> Self {
         let world_map: Map = world_map.unwrap_or_else(|| Map::new(1024, 1024));
-        let cells: HashMap<(i64,i64), Cell> = HashMap::new();
+        let cells = CellStore::new(world_map.width, world_map.height);
         let save_iter = 0;
-        
-        let energy_expanse = Self::get_energy_expanse();
-        let settings = SimulationSettings {
-            life_time,
-            energy_expanse,
-            polution_increase: 0.1f32, // useless
-            polution_decrease: 0.1f32, // useless
-            polution_critical_lvl: 15f32
-        };
+        let rng = stream_rng(settings.seed, STREAM_STEP, save_iter as u64);
 
         Simulation { 
             cells, 
@@ -163,275 +63,437 @@ impl Simulation {
             save_path,
             save_file_name,
             settings,
+            rng,
+            lineage: LineageLog::default(),
+            stats: StatsLog::default(),
+            events: Vec::new(),
+            observers: Vec::new(),
+            trajectory: None,
+        }
+    }
+
+    /// Redirect per-step views (and `<save_path>_back` states) to another place.
+    pub fn set_output(&mut self, save_path: Option<String>, save_file_name: Option<String>) {
+        if let Some(save_path) = save_path {
+            self.save_path = save_path;
         }
+        if let Some(save_file_name) = save_file_name {
+            self.save_file_name = save_file_name;
+        }
+        self.trajectory = None;
+    }
+
+    pub fn world_map(&self) -> &Map {
+        &self.world_map
+    }
+
+    pub fn cells(&self) -> impl Iterator<Item = &Cell> {
+        self.cells.iter()
     }
 
-    const WIN_W: usize = 5;
-    const WIN_H: usize = 5;
-    const PAD_VALUE: f32 = -1.0; // или 0.0
-    const HW: usize = Self::WIN_W / 2;
-    const HH: usize = Self::WIN_H / 2;
+    pub fn cell(&self, coord: &Coord) -> Option<&Cell> {
+        self.cells.get(coord)
+    }
+
+    pub fn cells_count(&self) -> usize {
+        self.cells.len()
+    }
+
+    pub fn settings(&self) -> &SimulationSettings {
+        &self.settings
+    }
+
+    pub fn events(&self) -> &[Event] {
+        &self.events
+    }
+
+    /// Have `observer` called with the events of every following step.
+    pub fn subscribe(&mut self, observer: impl Observer + 'static) {
+        self.observers.push(Box::new(observer));
+    }
+
+    fn notify(&mut self) {
+        for observer in &mut self.observers {
+            for event in &self.events {
+                observer.on_event(self.save_iter, event);
+            }
+        }
+    }
 
+    /// Square window of side `2 * radius + 1` centred at `coord`; cells outside the map
+    /// are filled according to `padding`.
     fn extract_window(
-        map: &Array2<f32>, // или другой тип элемента
-        coord: &Coord,      // { x: usize, y: usize }
+        map: &Array2<f32>,
+        coord: &Coord,
+        radius: usize,
+        padding: Padding,
     ) -> Array2<f32> {
-        // центральное окно размером WIN_H x WIN_W, с центром в coord
-        // в вашем примере вы брали от x-3..x+4 (7 ширина) и y-3..y+4
-        let map_h = map.nrows();
-        let map_w = map.ncols();
-
-        let src_x0 = coord.x.saturating_sub(Self::HW as i64) as usize;
-        let src_y0 = coord.y.saturating_sub(Self::HH as i64) as usize;
-        let src_x1 = (coord.x as usize + Self::HW + 1).min(map_w); // exclusive
-        let src_y1 = (coord.y as usize + Self::HH + 1).min(map_h);
+        let size = 2 * radius + 1;
+        let r = radius as i64;
+        let map_h = map.nrows() as i64;
+        let map_w = map.ncols() as i64;
+
+        // the window lies inside the map - plain copy
+        if coord.x >= r && coord.y >= r && coord.x + r < map_w && coord.y + r < map_h {
+            let x0 = (coord.x - r) as usize;
+            let y0 = (coord.y - r) as usize;
+            return map.slice(s![y0..y0 + size, x0..x0 + size]).to_owned();
+        }
 
-        let mut out = Array2::from_elem((Self::WIN_H, Self::WIN_W), Self::PAD_VALUE);
+        Array2::from_shape_fn((size, size), |(wy, wx)| {
+            let x = coord.x + wx as i64 - r;
+            let y = coord.y + wy as i64 - r;
+            if x >= 0 && x < map_w && y >= 0 && y < map_h {
+                return map[(y as usize, x as usize)];
+            }
+            match padding {
+                Padding::Constant(v) => v,
+                Padding::Edge => map[(y.clamp(0, map_h - 1) as usize, x.clamp(0, map_w - 1) as usize)],
+                Padding::Wrap => map[(y.rem_euclid(map_h) as usize, x.rem_euclid(map_w) as usize)],
+            }
+        })
+    }
 
-        if src_x0 < src_x1 && src_y0 < src_y1 {
-            let dst_x0 = Self::HW.saturating_sub(coord.x.saturating_sub(src_x0 as i64) as usize);
-            let dst_y0 = Self::HH.saturating_sub(coord.y.saturating_sub(src_y0 as i64) as usize);
-            let dst_x1 = dst_x0 + (src_x1 - src_x0);
-            let dst_y1 = dst_y0 + (src_y1 - src_y0);
+    /// Whether two cells are parts of the same plant. Cells of old saves have no organism,
+    /// there one feeding energy into the other makes them kin.
+    fn is_kin(world_map: &Map, a: &Cell, b: &Cell) -> bool {
+        if !a.organism.is_nil() || !b.organism.is_nil() {
+            return a.organism == b.organism;
+        }
+        let feeds = |from: &Cell, to: &Cell| {
+            world_map.neighbour(&from.pos, &from.out_dir).map(|c| c.to_tuple_xy()) == Some(to.pos.to_tuple_xy())
+        };
+        feeds(a, b) || feeds(b, a)
+    }
 
-            let src_view = map.slice(s![src_y0..src_y1, src_x0..src_x1]);
-            out.slice_mut(s![dst_y0..dst_y1, dst_x0..dst_x1]).assign(&src_view);
+    /// Collect everything the bud at `coord` perceives.
+    fn sense(&self, coord: &Coord) -> Input {
+        let cell = &self.cells[coord];
+        let sensors = &self.settings.sensors;
+        let neighbours = Direction::all_directions().map(|dir| {
+            let n = self.world_map.neighbour(coord, &dir)?;
+            self.cells.get(&n).map(|n| Neighbour {
+                kind: n.kind.index(),
+                kin: Self::is_kin(&self.world_map, cell, n),
+            })
+        });
+        // a torus has no border to pad
+        let padding = match self.world_map.topology {
+            Topology::Bounded => sensors.padding,
+            Topology::Torus => Padding::Wrap,
+        };
+        let (w, h) = (self.world_map.width as f32, self.world_map.height as f32);
+        let (x, y) = (coord.x as f32, coord.y as f32);
+
+        Input {
+            organic_poisoning: Self::extract_window(&self.world_map.organics, coord, sensors.radius, padding),
+            electric_poisoning: Self::extract_window(&self.world_map.electric, coord, sensors.radius, padding),
+            energy: cell.energy,
+            neighbours,
+            life_left: cell.life_time as f32 / self.settings.life_time as f32,
+            edge_distance: [y / h, (w - 1.0 - x) / w, (h - 1.0 - y) / h, x / w],
+            light: self.world_map.light[(coord.y as usize, coord.x as usize)],
         }
+    }
 
-        out
+    /// Add the first buds of a fresh world, each starting a lineage.
+    pub fn add_founders(&mut self, cells: Vec<Cell>) {
+        self.lineage.step = self.save_iter;
+        for cell in &cells {
+            self.lineage.birth(cell.organism, cell.genome_id, None);
+        }
+        self.add_cells(cells);
     }
 
     pub fn add_cells(&mut self, cells: Vec<Cell>) {
         for cell in cells {
-            self.cells.insert(cell.pos.to_tuple_xy(), cell);
+            self.cells.insert(cell);
         }
     }
 
-    fn increase_polution(world_map: &mut Map, coord: &Coord) {
-        let l_x = cmp::max(coord.x-1, 0) as usize;
-        let t_y = cmp::max(coord.y-1, 0) as usize;
-        let r_x = cmp::min(coord.x+2, world_map.width as i64) as usize;
-        let b_y = cmp::min(coord.y+2, world_map.height as i64) as usize;
-        let slice = s![t_y..b_y, l_x..r_x];
-        let mut area = world_map.organics.slice_mut(slice);
-        area += 0.1;
-        let mut area = world_map.electric.slice_mut(slice);
-        area += 0.1;
+    /// Spread `amount` of organics evenly over the 3x3 area around `coord`.
+    fn deposit_organics(world_map: &mut Map, coord: &Coord, amount: f32) {
+        let area = world_map.area(coord, 1);
+        let share = amount / area.len() as f32;
+        for i in area {
+            world_map.organics[i] += share;
+        }
+    }
+
+    /// The one way a cell leaves the world: its corpse rots into organics around it and
+    /// the death is recorded.
+    fn kill_cell(cells: &mut CellStore, world_map: &mut Map, settings: &SimulationSettings,
+                 events: &mut Vec<Event>, coord: &Coord, cause: DeathCause) -> Option<Cell> {
+        let cell = cells.remove(coord)?;
+        let corpse = &settings.corpse;
+        let matter = match cell.kind {
+            CellKind::Producer(_) => corpse.producer,
+            CellKind::Conductor => corpse.conductor,
+            CellKind::Storage(_) => corpse.storage,
+        };
+        // the energy of a prey went to the hunter
+        let energy = if cause == DeathCause::Eaten { 0.0 } else { cell.energy.max(0.0) };
+        Self::deposit_organics(world_map, coord, matter + corpse.energy_share * energy);
+        events.push(Event::Died { pos: coord.clone(), kind: cell.kind.index(), cause });
+        Some(cell)
+    }
+
+    fn kill(&mut self, coord: &Coord, cause: DeathCause) -> Option<Cell> {
+        Self::kill_cell(&mut self.cells, &mut self.world_map, &self.settings, &mut self.events, coord, cause)
     }
 
-    fn update_energy_dir(world_map: &Map, 
-                        coord: &Coord, 
-                        cell: &Cell, 
-                        cells: &HashMap<(i64,i64), Cell>) -> (Coord, Option<Direction>) {
-        let mut rec_coord = coord.shift(&cell.out_dir);
-        let mut rec_dir: Option<Direction> = Some(cell.out_dir.clone());
-        if !world_map.in_bounds(rec_coord.x, rec_coord.y) {
-            panic!("Cannot transfer energy out of map bounds!");
+    /// Take `amount` from every cell of `area` (not below zero) if there is anything to take.
+    fn drain(layer: &mut Array2<f32>, area: &[(usize, usize)], amount: f32) -> bool {
+        if area.iter().map(|&i| layer[i]).sum::<f32>() <= 0.0 {
+            return false;
         }
-        if !cells.contains_key(&rec_coord.to_tuple_xy()) {
-            // try to find another neighbour to transfer energy
-            for dir in Direction::all_directions() {
-                let rec_coord_tmp = coord.shift(&dir);
-                if cells.contains_key(&rec_coord_tmp.to_tuple_xy()) {
-                    let Some(cell) = cells.get(&rec_coord_tmp.to_tuple_xy()) else {
-                        panic!("There is not such cell!");
-                    };
-                    match cell.kind {
-                        CellKind::Producer(_) => continue,
-                        _ => {
-                            // new receiver found
-                            rec_coord = rec_coord_tmp;
-                            rec_dir = Some(dir.clone());
-                            break;
-                        }
-                    }
+        for &i in area {
+            layer[i] = (layer[i] - amount).max(0.0);
+        }
+        true
+    }
+
+    /// Sunlight of the current step; every leaf shades the cell north of it.
+    fn update_light(&mut self) {
+        self.world_map.update_light(self.save_iter, &self.settings.light);
+        let keep = 1.0 - self.settings.light.shade;
+        for cell in self.cells.iter() {
+            let CellKind::Producer(Producer { resource: ResourceType::Solar }) = cell.kind else { continue };
+            if let Some(c) = self.world_map.neighbour(&cell.pos, &Direction::North) {
+                self.world_map.light[(c.y as usize, c.x as usize)] *= keep;
+            }
+        }
+    }
+
+    /// Coordinates of all cells, row by row.
+    pub fn get_coords(&self) -> Vec<Coord> {
+        self.cells.coords()
+    }
+
+    fn storage(&self, coord: &Coord) -> &Storage {
+        let CellKind::Storage(s) = &self.cells[coord].kind else {
+            panic!("There is no bud at {coord:?}!");
+        };
+        s
+    }
+
+    /// Actions and the new memory of every bud in `buds`. Inputs are sensed in parallel,
+    /// then the buds sharing a genome run through it as one batch.
+    fn decide(&self, buds: &[Coord]) -> Vec<(Vec<Action>, Array1<f32>)> {
+        let sensors = &self.settings.sensors;
+        let inputs: Vec<Array1<f32>> = buds.par_iter()
+            .map(|coord| self.sense(coord).flatten(sensors))
+            .collect();
+
+        // groups in order of their first bud, so the batches are the same every run
+        let mut groups: Vec<Vec<usize>> = Vec::new();
+        let mut group_of: HashMap<*const (), usize> = HashMap::new();
+        for (i, coord) in buds.iter().enumerate() {
+            let genome = Arc::as_ptr(&self.storage(coord).genome) as *const ();
+            let g = *group_of.entry(genome).or_insert_with(|| {
+                groups.push(Vec::new());
+                groups.len() - 1
+            });
+            groups[g].push(i);
+        }
+
+        let outputs: Vec<(Vec<usize>, Array2<f32>, Array2<f32>)> = groups.into_par_iter().map(|members| {
+            let genome = &self.storage(&buds[members[0]]).genome;
+            let n_state = genome.state_size();
+            let mut batch = Array2::zeros((members.len(), sensors.input_size()));
+            let mut states = Array2::zeros((members.len(), n_state));
+            for (row, &i) in members.iter().enumerate() {
+                batch.row_mut(row).assign(&inputs[i]);
+                let memory = &self.storage(&buds[i]).memory;
+                // a memory of another size starts over from zeros
+                if memory.len() == n_state {
+                    states.row_mut(row).assign(memory);
                 }
             }
-            // if let Some(d) = &rec_dir { println!("contains!"); }
-            // else { println!("Did not find!"); }
+            let out = genome.forward_batch(&batch, &mut states);
+            (members, out, states)
+        }).collect();
+
+        let mut decisions: Vec<(Vec<Action>, Array1<f32>)> = (0..buds.len()).map(|_| Default::default()).collect();
+        for (members, out, states) in outputs {
+            for (row, i) in members.into_iter().enumerate() {
+                decisions[i] = (Storage::decode(out.row(row)), states.row(row).to_owned());
+            }
         }
-        (rec_coord, rec_dir)
+        decisions
     }
 
-    fn get_slice(center_coord : &Coord, r: i64, h: usize, w: usize) -> SliceInfo<[SliceInfoElem; 2], Dim<[usize; 2]>, Dim<[usize; 2]>> {
-        let l_x = cmp::max(center_coord.x-r, 0) as usize;
-        let t_y = cmp::max(center_coord.y-r, 0) as usize;
-        let r_x = cmp::min(center_coord.x+r+1, w as i64) as usize;
-        let b_y = cmp::min(center_coord.y+r+1, h as i64) as usize;
-        s![t_y..b_y, l_x..r_x]
+    /// Energy a producer at `coord` makes this step; roots and antennas drain the pollution
+    /// they feed on.
+    fn produce(&mut self, coord: &Coord, resource: ResourceType) -> f32 {
+        let energy = &self.settings.energy;
+        match resource {
+            ResourceType::Solar => energy.solar_yield * self.world_map.light[(coord.y as usize, coord.x as usize)],
+            ResourceType::Organic => {
+                let area = self.world_map.area(coord, 1);
+                if Self::drain(&mut self.world_map.organics, &area, energy.resource_drain) { energy.organic_yield } else { 0.0 }
+            },
+            ResourceType::Electricity => {
+                let area = self.world_map.area(coord, 1);
+                if Self::drain(&mut self.world_map.electric, &area, energy.resource_drain) { energy.electric_yield } else { 0.0 }
+            },
+        }
     }
 
-    pub fn get_coords(&self) -> Vec<Coord> {
-        let mut coords: Vec<Coord> = Vec::with_capacity(self.cells.len());
-        for ((x, y), _) in &self.cells {
-            coords.push(Coord { x: *x, y: *y });
+    /// Every plant body feeds its bud: the energy of each producer and conductor goes
+    /// to the nearest bud of the same organism, losing `transport_loss` per hop on the way.
+    /// Producers are leaves, energy never passes through them. Cells with no way to a bud die.
+    /// The bud pays the upkeep of its whole body and starves once its energy is negative.
+    fn flow_energy(&mut self) {
+        let index = |c: &Coord| (c.y as usize, c.x as usize);
+        // bud every reached cell feeds and hops to it
+        let mut route: Array2<Option<(usize, i32)>> =
+            Array2::from_elem((self.world_map.height, self.world_map.width), None);
+        let buds: Vec<Coord> = self.get_coords().into_iter()
+            .filter(|c| matches!(self.cells[c].kind, CellKind::Storage(_)))
+            .collect();
+        let mut queue: VecDeque<Coord> = VecDeque::new();
+        for (b, coord) in buds.iter().enumerate() {
+            route[index(coord)] = Some((b, 0));
+            queue.push_back(coord.clone());
+        }
+        while let Some(coord) = queue.pop_front() {
+            let (b, hops) = route[index(&coord)].expect("queued cells are routed");
+            for dir in Direction::all_directions() {
+                let Some(next) = self.world_map.neighbour(&coord, &dir) else { continue };
+                if route[index(&next)].is_some() { continue; }
+                let Some(cell) = self.cells.get(&next) else { continue };
+                // legacy cells have no organism, they are joined only along the energy flow
+                if !Self::is_kin(&self.world_map, &self.cells[&coord], cell)
+                    || matches!(cell.kind, CellKind::Storage(_)) { continue; }
+                let cell = self.cells.get_mut(&next).expect("the cell is alive");
+                route[index(&next)] = Some((b, hops + 1));
+                // energy goes back the way the search came
+                cell.out_dir = dir.oposite();
+                if matches!(cell.kind, CellKind::Conductor) {
+                    queue.push_back(next);
+                }
+            }
+        }
+
+        let keep = 1.0 - self.settings.energy.transport_loss;
+        let upkeep = self.settings.upkeep.clone();
+        let mut income = vec![0f32; buds.len()];
+        let mut bodies: Vec<Vec<Coord>> = vec![Vec::new(); buds.len()];
+        for coord in self.get_coords() {
+            let (resource, cost) = match &self.cells[&coord].kind {
+                CellKind::Producer(p) => (Some(p.resource), upkeep.producer),
+                CellKind::Conductor => (None, upkeep.conductor),
+                CellKind::Storage(_) => continue,
+            };
+            let Some((b, hops)) = route[index(&coord)] else {
+                // kill cell (there is no receiver)
+                self.kill(&coord, DeathCause::Isolation);
+                continue;
+            };
+            let produced = resource.map_or(0.0, |r| self.produce(&coord, r));
+            let stored = std::mem::take(&mut self.cells.get_mut(&coord).expect("the cell is alive").energy);
+            let delivered = (produced + stored) * keep.powi(hops);
+            if delivered > 0.0 {
+                self.events.push(Event::EnergyTransferred { from: coord.clone(), to: buds[b].clone(), amount: delivered });
+            }
+            income[b] += delivered - cost;
+            bodies[b].push(coord);
+        }
+        for (b, coord) in buds.iter().enumerate() {
+            let bud = self.cells.get_mut(coord).expect("the bud is alive");
+            bud.energy += income[b] - upkeep.storage;
+            if bud.energy < 0.0 {
+                // the body it fed starves with it
+                self.kill(coord, DeathCause::Starvation);
+                for cell in &bodies[b] {
+                    self.kill(cell, DeathCause::Starvation);
+                }
+            }
         }
-        coords
     }
 
+    /// One tick: cells age and die in a shuffled order, plants feed their buds, then every
+    /// bud decides in parallel and the decisions are applied in a fixed priority order.
     pub fn step(&mut self) {
+        // every step has its own stream, so a loaded save continues the same trajectory
+        self.rng = stream_rng(self.settings.seed, STREAM_STEP, self.save_iter as u64);
+        self.lineage.step = self.save_iter;
+        self.events.clear();
+        self.update_light();
+        self.world_map.update_pollution(&self.settings.pollution);
+
         let coords: Vec<Coord> = self.get_coords();
-        let order = shuffled_indices(self.cells.len());
+        let order = shuffled_indices(self.cells.len(), &mut self.rng);
 
         // decrease life time of all existing cells
-        for (_, cell) in self.cells.iter_mut() {
+        for cell in self.cells.iter_mut() {
             cell.life_time -= 1;
         }
 
-        let mut deleted_cells_count = 0;
         for i in order {
-            let coord = coords[i].clone();
-            let key = coord.to_tuple_xy();
-            if !self.cells.contains_key(&key) { continue; }
-
-            let kind = {
-                let Some(cell) = self.cells.get(&key) else {
-                    panic!("There is no cell with such coords {coord:?}!");
-                };
-                
-                // if it's dead
-                if cell.life_time <= 0 {
-                    self.cells.remove(&key);
-                    Self::increase_polution(&mut self.world_map, &coord);
-                    deleted_cells_count += 1;
-                    // println!("delete from coord: (x={}, y={})", coord.x, coord.y);
-                    continue;
-                }
+            let coord = &coords[i];
+            let Some(cell) = self.cells.get(coord) else { continue };
 
-                &cell.kind
-            };
-
-            // check if it's too poluted to live here
-            match kind {
-                CellKind::Conductor => {
-                    let (org, elc) = self.world_map.is_lvl_critical(
-                        coord.x as usize, coord.y as usize, 
-                        self.settings.polution_critical_lvl);
-                    if org || elc {
-                        self.cells.remove(&key);
-                        continue;
-                    }
-                },
-                CellKind::Producer(p) => {
-                    let (org, elc) = self.world_map.is_lvl_critical(
-                        coord.x as usize, coord.y as usize, 
-                        self.settings.polution_critical_lvl);
-                    match p.resource {
-                        ResourceType::Solar => {
-                            if org || elc { self.cells.remove(&key); continue; }
-                        },
-                        ResourceType::Organic => {
-                            if elc { self.cells.remove(&key); continue; }
-                        },
-                        ResourceType::Electricity => {
-                            if org { self.cells.remove(&key); continue; }
-                        },
-                    }
-                },
-                _ => {}
+            // if it's dead
+            if cell.life_time <= 0 {
+                self.kill(coord, DeathCause::Age);
+                continue;
             }
 
-            match kind {
-                CellKind::Producer(p) => {
-                    // calculate direction to store energy
-                    let (rec_coord, rec_dir) = Simulation::update_energy_dir(&self.world_map, &coord, &self.cells[&key], &self.cells);
-                    if !self.cells.contains_key(&rec_coord.to_tuple_xy()) {
-                        // kill cell (there is no receiver)
-                        self.cells.remove(&key);
-                        continue;
-                    }
-                    // println!("Producer cell: {:?} {:?}", rec_coord, rec_dir);
-
-                    // produced energy
-                    let mut energy_produced = 0f32;
-                    match p.resource {
-                        ResourceType::Solar => energy_produced = 0.1f32,
-                        ResourceType::Organic => {
-                            let slice = Self::get_slice(&coord, 1, self.world_map.height, self.world_map.width);
-                            let mut area =  self.world_map.organics.slice_mut(slice);
-                            if area.sum() > 0.0f32 {
-                                area -= 0.2;
-                                area.map_inplace(|v| if *v < 0.0 { *v = 0.0 });
-                                energy_produced = 0.1f32;
-                            }
-                        },
-                        ResourceType::Electricity => {
-                            let slice = Self::get_slice(&coord, 1, self.world_map.height, self.world_map.width);
-                            let mut area =  self.world_map.electric.slice_mut(slice);
-                            if area.sum() > 0.0f32 {
-                                area -= 0.2;
-                                area.map_inplace(|v| if *v < 0.0 { *v = 0.0 });
-                                energy_produced = 0.1f32;
-                            }
-                        },
-                    }
-                    
-                    
-                    let rec_key = rec_coord.to_tuple_xy();
-                    let rec_cell = match self.cells.get_mut(&rec_key) {
-                        Some(c) => c,
-                        None => panic!(), // no receiver — skip
-                    };
-                    rec_cell.energy += energy_produced;
-
-                    // actual direction for energy flow set
-                    if let Some(cell_mut) = self.cells.get_mut(&key) {
-                        cell_mut.out_dir = rec_dir.expect("rec_dir is None");
-                    } else {
-                        panic!("There is no cell with such coords  {coord:?}!");
-                    }
-                },
-                CellKind::Conductor => {
-                    let (rec_coord, rec_dir) = {
-                        let cell_ref = &self.cells[&key];
-                        Simulation::update_energy_dir(&self.world_map, &coord, cell_ref, &self.cells)
-                    };
-                    if !self.cells.contains_key(&rec_coord.to_tuple_xy()) {
-                        // kill cell (there is no receiver)
-                        self.cells.remove(&key);
-                        continue;
-                    }
-                    let rec_key = rec_coord.to_tuple_xy();
-                    let energy = self.cells[&key].energy;
-                    
-                    // energy increase
-                    if let Some(c) = self.cells.get_mut(&rec_key) {
-                        c.energy += energy;
-                    } else { panic!(); }
-                    // energy to zero
-                    if let Some(c) = self.cells.get_mut(&coord.to_tuple_xy()) {
-                        c.energy = 0f32;
-                    } else { panic!(); }
-                    // actual direction for energy flow set
-                    if let Some(c) = self.cells.get_mut(&key) {
-                        c.energy = 0.0;
-                        c.out_dir = rec_dir.expect("rec_dir is None");
-                    } else { panic!(); }
-                },
-                CellKind::Storage(s) => {
-                    let Some(cell_ref) = self.cells.get(&key) else { panic!() };
-
-                    let slice_1 = Self::extract_window(&self.world_map.organics, &coord);
-                    let slice_2 = Self::extract_window(&self.world_map.electric, &coord);
-
-                    let input = Input {
-                        organic_poisoning: slice_1.to_owned(),
-                        electric_poisoning: slice_2.to_owned(),
-                        energy: cell_ref.energy
-                    };
-                    let actions = s.get_decision(input);
-                    Self::execute_actions(&mut self.cells, &self.world_map, actions, coord, &self.settings);
+            // check if it's too poluted to live here
+            let (org, elc) = self.world_map.is_lvl_critical(
+                coord.x as usize, coord.y as usize,
+                self.settings.pollution.critical_lvl);
+            let poisoned = match &cell.kind {
+                CellKind::Conductor => org || elc,
+                CellKind::Producer(p) => match p.resource {
+                    ResourceType::Solar => org || elc,
+                    ResourceType::Organic => elc,
+                    ResourceType::Electricity => org,
                 },
+                CellKind::Storage(_) => false,
+            };
+            if poisoned {
+                self.kill(coord, DeathCause::Pollution);
             }
         }
         // println!("Cells count: {}, Coodrs count: {}", self.cells.len(), new_coords.len());
-        // println!("Deleted cells count: {}", deleted_cells_count);
+
+        self.flow_energy();
+
+        // every bud decides against the same frozen world, in parallel
+        let buds: Vec<Coord> = self.get_coords().into_iter()
+            .filter(|c| matches!(self.cells[c].kind, CellKind::Storage(_)))
+            .collect();
+        let mut decisions = self.decide(&buds);
+
+        // decisions are applied one by one: the richest bud first, ties broken by the step stream
+        let mut priority: Vec<(f32, u64, usize)> = buds.iter().enumerate()
+            .map(|(i, c)| (self.cells[c].energy, self.rng.random(), i))
+            .collect();
+        priority.sort_unstable_by(|a, b| b.0.total_cmp(&a.0).then(a.1.cmp(&b.1)));
+
+        // buds that already acted or were born this step
+        let mut acted: HashSet<(i64, i64)> = HashSet::new();
+        for (_, _, i) in priority {
+            let coord = buds[i].clone();
+            if acted.contains(&coord.to_tuple_xy()) { continue; }
+            // eaten by a bud that acted earlier
+            let Some(cell) = self.cells.get_mut(&coord) else { continue };
+            let CellKind::Storage(s) = &mut cell.kind else { continue };
+            let (actions, memory) = std::mem::take(&mut decisions[i]);
+            s.memory = memory;
+            let new_buds = self.execute_actions(actions, coord);
+            acted.extend(new_buds.iter().map(|c| c.to_tuple_xy()));
+        }
+        self.stats.record(StepStats::collect(self.save_iter, self.cells.iter(), &self.world_map, &self.events));
+        self.notify();
     }
 
-    fn execute_actions(cells: &mut HashMap<(i64, i64), Cell>, 
-                        world_map: &Map, 
-                        actions: Vec<Action>, 
-                        coord: Coord, settings: &SimulationSettings) -> Coord {
+    /// Apply the `actions` of the bud at `coord`; returns where the bud ended up, then its daughters.
+    fn execute_actions(&mut self, actions: Vec<Action>, coord: Coord) -> Vec<Coord> {
+        let Self { cells, world_map, settings, rng, lineage, events, .. } = self;
         let mut final_bud_coord = coord.clone();
         let mut need_energy = 0f32;
         let mut action_is_valid = [true; 4];
@@ -439,27 +501,29 @@ impl Simulation {
 
         let mut bud_counter = 0;
         let mut bud_dirs: Vec<Direction> = Vec::new();
+        let mut daughters: Vec<Coord> = Vec::new();
 
         for (i, action) in actions.iter().enumerate() {
-            let action_coord = coord.shift(&action.0);
-            let action_coord_key = action_coord.to_tuple_xy();
-            new_cells_coords.push(action_coord.clone());
-
             // вышли за рамки мира
-            if !world_map.in_bounds(action_coord.x, action_coord.y) {
+            let Some(action_coord) = world_map.neighbour(&coord, &action.0) else {
+                new_cells_coords.push(coord.shift(&action.0));
                 action_is_valid[i] = false;
                 continue;
-            }
+            };
+            new_cells_coords.push(action_coord.clone());
             
-            if cells.contains_key(&action_coord_key) {
+            if cells.contains(&action_coord) {
                 match action.1 {
                     3 => {
                         // bud может съесть
-                        let cell_killed_energy = cells.get(&action_coord_key).expect("cannot be None").energy;
-                        let cell_hunter = cells.get_mut(&coord.to_tuple_xy()).expect("cannot be None");
-                        cell_hunter.energy += cell_killed_energy * 0.7;
                         // delete cell from world
-                        cells.remove(&action_coord_key).expect("there was not cell there");
+                        let cell_killed = Self::kill_cell(cells, world_map, settings, events,
+                                                          &action_coord, DeathCause::Eaten)
+                            .expect("there was not cell there");
+                        let cell_hunter = cells.get_mut(&coord).expect("cannot be None");
+                        let amount = cell_killed.energy * settings.energy.hunt_efficiency;
+                        cell_hunter.energy += amount;
+                        events.push(Event::EnergyTransferred { from: action_coord.clone(), to: coord.clone(), amount });
                     },
                     _ => {
                         // уже что-то есть - нельзя
@@ -475,9 +539,9 @@ impl Simulation {
                 // 1 - root
                 // 2 - antena
                 // 3 - move/create bud here
-                0..=2 => { need_energy += settings.energy_expanse["producer"]; },
+                0..=2 => { need_energy += settings.energy.producer_cost; },
                 3 => { 
-                    need_energy += settings.energy_expanse["storage"]; 
+                    need_energy += settings.energy.storage_cost; 
                     bud_counter += 1; 
                     bud_dirs.push(action.0.clone()); 
                 },
@@ -485,57 +549,75 @@ impl Simulation {
             }
         }
 
-        let cell_key = coord.to_tuple_xy();
-        let cell = match cells.get(&cell_key) {
+        let cell = match cells.get(&coord) {
             Some(c) => c,
             None => panic!("There is no cell with such coords!"),
         };
-        if need_energy > cell.energy { return final_bud_coord; }
+        let (organism, genome_id) = (cell.organism, cell.genome_id);
+        if need_energy > cell.energy { return vec![final_bud_coord]; }
+        // growth is paid by the bud, daughters get their share out of it
+        cells.get_mut(&coord).expect("the bud is alive").energy -= need_energy;
         
         // there is some buds to create/move
         if bud_counter > 0 {
             // chose the main direction and new buds directions
-            let mut r = rng();
-            let main_bud_ind = r.random_range(0..bud_counter);
+            let main_bud_ind = rng.random_range(0..bud_counter);
 
             // move main bud
-            final_bud_coord = coord.shift(&bud_dirs[main_bud_ind]);
+            final_bud_coord = world_map.neighbour(&coord, &bud_dirs[main_bud_ind]).expect("bud moves inside the map");
             
             let conductor = Cell {
                 kind: CellKind::Conductor,
+                organism,
+                genome_id,
                 life_time: settings.life_time,
                 pos: coord.clone(),
                 out_dir: bud_dirs[main_bud_ind].clone(),
                 energy: 0f32
             };
 
-            let old_cell = cells.remove(&cell_key).expect("execute: there is not cell in this coords??");
-            cells.insert(cell_key, conductor);
-            cells.insert(final_bud_coord.to_tuple_xy(), old_cell);
+            let mut old_cell = cells.remove(&coord).expect("execute: there is not cell in this coords??");
+            old_cell.pos = final_bud_coord.clone();
+            cells.insert(conductor);
+            cells.insert(old_cell);
+            events.push(Event::Moved { organism, from: coord.clone(), to: final_bud_coord.clone() });
+            events.push(Event::Born { pos: coord.clone(), kind: CellKind::Conductor.index(), organism });
 
             // create extra buds
-            for i in 0..bud_counter {
+            for (i, bud_dir) in bud_dirs.iter().enumerate() {
                 if i == main_bud_ind { continue; }
-                let new_bud_coord = coord.shift(&bud_dirs[i]);
+                let new_bud_coord = world_map.neighbour(&coord, bud_dir).expect("bud grows inside the map");
 
-                let parent_key = final_bud_coord.to_tuple_xy();
-                let parent_cell = cells.get(&parent_key).expect("There is no cell with such coords.");
-                let genome = match &parent_cell.kind {
+                let parent_cell = cells.get(&final_bud_coord).expect("There is no cell with such coords.");
+                let (storage, daughter_genome) = match &parent_cell.kind {
                     CellKind::Storage(st) => {
-                        if rng().random_bool(0.15) { st.genome.mutate() }
-                        else { st.genome.clone() }
+                        let (genome, daughter_genome) = if rng.random_bool(settings.mutation.probability) {
+                            (Arc::from(st.genome.mutate(rng, settings.mutation.std)), new_id(rng))
+                        }
+                        else { (st.genome.clone(), genome_id) };
+                        // daughters start from the parent's memory
+                        (Storage { genome, memory: st.memory.clone() }, daughter_genome)
                     }
                     _ => { panic!("Is not bud cell here!!!"); }
                 };
+                let daughter = new_id(rng);
+                lineage.birth(daughter, daughter_genome, Some((organism, genome_id)));
+                if daughter_genome != genome_id {
+                    events.push(Event::Mutated { organism: daughter, parent_genome: genome_id, genome: daughter_genome });
+                }
                 
                 let new_cell = Cell {
-                    kind: CellKind::Storage(Storage { genome }),
+                    kind: CellKind::Storage(storage),
+                    organism: daughter,
+                    genome_id: daughter_genome,
                     life_time: settings.life_time,
                     pos: new_bud_coord.clone(),
-                    out_dir: bud_dirs[i].clone(),
-                    energy: settings.energy_expanse["storage"]*0.8
+                    out_dir: bud_dir.clone(),
+                    energy: settings.energy.storage_cost * settings.energy.daughter_share
                 };
-                cells.insert(new_bud_coord.to_tuple_xy(), new_cell);
+                events.push(Event::Born { pos: new_bud_coord.clone(), kind: new_cell.kind.index(), organism: daughter });
+                cells.insert(new_cell);
+                daughters.push(new_bud_coord);
             }
         }
 
@@ -553,121 +635,105 @@ impl Simulation {
             let pos = new_cells_coords[i].clone();
             let out_dir = action.0.oposite().clone();
             
+            events.push(Event::Born { pos: pos.clone(), kind: kind.index(), organism });
             let cell = Cell {
                 kind,
+                organism,
+                genome_id,
                 life_time: settings.life_time,
                 pos,
                 out_dir,
                 energy: 0f32
             };
-            cells.insert(cell.pos.to_tuple_xy(), cell);
+            cells.insert(cell);
         }
 
-        final_bud_coord
+        daughters.insert(0, final_bud_coord);
+        daughters
     }
 
-    pub fn save_view(&self, overwrite: bool) -> std::io::Result<()> {
-        // println!("saving...");
-        ensure_dir(Path::new(&self.save_path)).expect("Cannot ensure save directory!");
-        let filename = format!("{}/{}_meta.txt", self.save_path, self.save_file_name);
-        let path = Path::new(&filename);
-        if !path.exists() || overwrite {
-            let f = OpenOptions::new()
-                .create(true)
-                .write(true)
-                .truncate(true)
-                .open(path)?;
-            let mut w = BufWriter::new(f);
-            // запись размеров: "width height"
-            writeln!(w, "{},{}", self.world_map.height, self.world_map.width)?;
-            w.flush()?;
+    /// Append the cells of this step to the trajectory `<save_path>/<save_file_name>.traj`;
+    /// `overwrite` starts the file over. The file stays open until the output changes.
+    pub fn save_view(&mut self, overwrite: bool) -> Result<(), Box<dyn Error>> {
+        let (width, height) = (self.world_map.width, self.world_map.height);
+        if overwrite || self.trajectory.is_none() {
+            ensure_dir(Path::new(&self.save_path)).expect("Cannot ensure save directory!");
+            let path = Path::new(&self.save_path).join(format!("{}.{}", self.save_file_name, TRAJECTORY_EXT));
+            self.trajectory = Some(TrajectoryWriter::open(&path, width, height, overwrite)?);
         }
+        let mut grid = vec![0u8; width * height];
+        for cell in self.cells.iter() {
+            grid[cell.pos.y as usize * width + cell.pos.x as usize] = cell.kind.index() as u8 + 1;
+        }
+        self.trajectory.as_mut().expect("opened above").write(self.save_iter, &grid)
+    }
 
-        let filename = format!("{}/{}_{}.csv", self.save_path, self.save_file_name, self.save_iter);
-        let path = Path::new(&filename);
-
-        let file = OpenOptions::new()
-            .create(true)
-            .write(true)
-            .truncate(true)
-            .open(path)?;
-        let mut w = BufWriter::new(file);
+    /// Write the whole state to `<save_path>_back/state.bin` and append the births and the
+    /// per-step metrics since the previous save to `lineage.csv` and `stats.csv` next to it;
+    /// the trajectory is flushed up to the saved step.
+    pub fn save_state(&mut self, overwrite: bool) -> Result<(), Box<dyn Error>> {
+        let save_path = format!("{}_back", self.save_path);
+        ensure_dir(Path::new(&save_path)).expect("save_state: Cannot ensure save directory!");
+        self.lineage.flush(&Path::new(&save_path).join(LINEAGE_FILE))?;
+        self.stats.flush(&Path::new(&save_path).join(STATS_FILE))?;
+        if let Some(trajectory) = &mut self.trajectory {
+            trajectory.flush()?;
+        }
 
-        // записываем только занятые клетки: "x y"
-        for (&(x, y), _cell) in &self.cells {
-            writeln!(w, "{},{},{}", x, y, _cell.kind.str())?;
+        let path = Path::new(&save_path).join(SNAPSHOT_FILE);
+        if path.exists() && !overwrite {
+            return Ok(());
         }
 
-        w.flush()?;
-        Ok(())
+        SnapshotRef::new(self.save_iter, &self.save_path, &self.save_file_name,
+                         &self.settings, &self.world_map, self.cells.iter())
+            .write(&path)
     }
 
-    pub fn save_state(&self, overwrite: bool) -> std::io::Result<()> {
-        let save_path = format!("{}_back", self.save_path);
-        ensure_dir(Path::new(&save_path)).expect("save_state: Cannot ensure save directory!");
-        
-        // save map info
-        let map_path = Path::new(save_path.as_str()).join("map");
-        ensure_dir(Path::new(&map_path)).expect("save_state: Cannot ensure save directory!");
-        self.world_map.save(map_path.as_path(), overwrite).expect("cannot save map to file!");
-
-        // simulation dir
-        let sim_path = Path::new(save_path.as_str()).join("sim");
-        ensure_dir(Path::new(&sim_path)).expect("save_state: Cannot ensure save directory!");
-
-        // save meta info about simulation
-        let path = sim_path.join("simulation_meta.txt");
-        if !path.exists() || overwrite {
-            let f = OpenOptions::new()
-                .create(true)
-                .write(true)
-                .truncate(true)
-                .open(path)?;
-            let mut w = BufWriter::new(f);
-            // запись размеров: "width height"
-            writeln!(w, "{},{}", self.world_map.height, self.world_map.width)?;
-            writeln!(w, "{}", self.save_iter)?;
-            writeln!(w, "{}", self.save_path)?;
-            writeln!(w, "{}", self.save_file_name)?;
-            w.flush()?;
-        }
-
-        // save simulation settings
-        let path = sim_path.join("simulation_settings.txt");
-        self.settings.save(path.as_path(), overwrite).expect("cannot save simulation settings!!!");
-
-        // save cells
-        let path = sim_path.join("cells");
-        ensure_dir(path.as_path()).expect("save_state: Cannot ensure save directory!");
-        self.save_cells(path.as_path(), overwrite).expect("cannot save cells!!!");
-
-        Ok(())
-    }
-
-    fn save_cells(&self, path: &Path, overwrite: bool) -> std::io::Result<()> {
-        for (i, (coord, cell)) in self.cells.iter().enumerate() {
-            let cell_path = path.join(format!("cell_{}", i));
-            ensure_dir(cell_path.as_path()).expect("save_state: Cannot ensure save directory!");
-            cell.save(cell_path.as_path(), overwrite).expect("save_state: cannot save cell to cell_dir");
-
-            // save_coords
-            let coord_path: std::path::PathBuf = cell_path.join("coord.txt");
-            if !coord_path.exists() || overwrite {
-                let f = OpenOptions::new()
-                    .create(true)
-                    .write(true)
-                    .truncate(true)
-                    .open(coord_path)?;
-                let mut w = BufWriter::new(f);
-                // запись размеров: "width height"
-                writeln!(w, "{},{}", coord.0, coord.1)?;
-                w.flush()?;
-            }
+    /// Whether `save_path` holds anything `load` would read.
+    pub fn save_exists(save_path: &Path) -> bool {
+        save_path.is_file() || save_path.join(SNAPSHOT_FILE).exists() || save_path.join("map").exists()
+    }
+
+    /// Load a state from a snapshot file, a directory holding `state.bin`,
+    /// or a directory in the legacy one-folder-per-cell layout.
+    pub fn load(save_path: &Path) -> Result<Self, Box<dyn Error>> {
+        let snapshot_path = if save_path.is_file() {
+            save_path.to_path_buf()
+        } else {
+            save_path.join(SNAPSHOT_FILE)
+        };
+        let legacy = !snapshot_path.exists();
+        let snapshot = if legacy { Self::read_legacy(save_path)? } else { Snapshot::read(&snapshot_path)? };
+        snapshot.validate().map_err(|e| format!("{:?} is not a valid save: {}", save_path, e))?;
+
+        let mut sim = Self::new(Some(snapshot.map),
+                                snapshot.save_path,
+                                snapshot.save_file_name,
+                                snapshot.settings);
+        sim.save_iter = snapshot.save_iter;
+        sim.rng = stream_rng(sim.settings.seed, STREAM_STEP, sim.save_iter as u64);
+        sim.add_cells(snapshot.cells);
+        if legacy {
+            sim.share_genomes();
         }
-        Ok(())
+        Ok(sim)
     }
 
-    pub fn load(save_path: &std::path::Path) -> Result<Self, Box<dyn std::error::Error>> {
+    /// Let buds of a legacy save with equal genomes share one copy, so they are batched
+    /// together; snapshots keep the sharing themselves.
+    fn share_genomes(&mut self) {
+        let mut seen: HashMap<Vec<u8>, Arc<dyn Controller>> = HashMap::new();
+        for cell in self.cells.iter_mut() {
+            let CellKind::Storage(st) = &mut cell.kind else { continue };
+            let bytes = bincode::serde::encode_to_vec(st.genome.record(), bincode::config::standard())
+                .expect("genome is serializable");
+            st.genome = seen.entry(bytes).or_insert_with(|| st.genome.clone()).clone();
+        }
+    }
+
+    /// Read a save in the legacy one-folder-per-cell layout.
+    fn read_legacy(save_path: &Path) -> Result<Snapshot, Box<dyn Error>> {
         // load map
         let map_path = save_path.join("map");
         let world_map = Map::load(&map_path)?;
@@ -706,28 +772,32 @@ impl Simulation {
         }
 
         // load settings
-        let settings_path = sim_path.join("simulation_settings.txt");
-        let settings = SimulationSettings::load(&settings_path)?;
+        let settings_path = sim_path.join("simulation_settings.toml");
+        let settings = if settings_path.exists() {
+            SimulationSettings::load(&settings_path)?
+        } else {
+            SimulationSettings::load_legacy(&sim_path.join("simulation_settings.txt"))?
+        };
 
         // load cells
         let cells_path = sim_path.join("cells");
         let cells = Self::load_cells(&cells_path)?;
 
-        Ok(Self {
-            world_map,
-            settings,
-            cells,
+        Ok(Snapshot {
             save_iter,
             save_path: save_path_str,
-            save_file_name
+            save_file_name,
+            settings,
+            map: world_map,
+            cells,
         })
     }
 
-    fn load_cells(path: &std::path::Path) -> Result<HashMap<(i64, i64), Cell>, Box<dyn std::error::Error>> {
+    fn load_cells(path: &std::path::Path) -> Result<Vec<Cell>, Box<dyn std::error::Error>> {
         if !path.exists() {
             panic!("there is not saved cells here!");
         }
-        let mut cells: HashMap<(i64, i64), Cell> = HashMap::new();
+        let mut cells = Vec::new();
         // перебираем папки cell_*
         for entry in std::fs::read_dir(path)? {
             let entry = entry?;
@@ -746,13 +816,13 @@ impl Simulation {
                 let y: i64 = parts[1].parse()?;
                 Coord { x, y }
             } else {
-                // fallback: try parse index from folder name
-                let name = entry.file_name().into_string().unwrap_or_default();
                 // координаты 0,0 по умолчанию
                 Coord { x: 0, y: 0 }
             };
-            let cell = Cell::load(&cell_dir)?;
-            cells.insert(coord.to_tuple_xy(), cell);
+            let mut cell = Cell::load(&cell_dir)?;
+            // the folder's coord.txt is what placed the cell
+            cell.pos = coord;
+            cells.push(cell);
         }
         Ok(cells)
     }
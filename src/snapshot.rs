@@ -0,0 +1,399 @@
+use ndarray::Array1;
+use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, HashSet};
+use std::error::Error;
+use std::fs::{self, File};
+use std::io::{BufReader, BufWriter, Read, Write};
+use std::path::Path;
+use std::sync::Arc;
+use uuid::Uuid;
+
+use crate::cells::{Cell, CellKind, Producer, Storage};
+use crate::common::{Coord, Direction};
+use crate::config::SimulationSettings;
+use crate::controller::{Controller, ControllerRecord, ControllerRef};
+use crate::map::Map;
+
+/// File name of the snapshot inside a state directory.
+pub const SNAPSHOT_FILE: &str = "state.bin";
+
+const MAGIC: &[u8; 8] = b"PLWSNAP\0";
+/// Bumped on every change of the serialized layout.
+pub const SNAPSHOT_VERSION: u32 = 13;
+
+/// Borrowed view of a simulation, written without copying the map or cells. Every
+/// genome is written once in `genomes`, buds refer to it by index.
+#[derive(Serialize)]
+pub struct SnapshotRef<'a> {
+    save_iter: usize,
+    save_path: &'a str,
+    save_file_name: &'a str,
+    settings: &'a SimulationSettings,
+    map: &'a Map,
+    genomes: Vec<ControllerRef<'a>>,
+    cells: Vec<CellRef<'a>>,
+}
+
+/// Owned counterpart of `SnapshotRef`, field for field.
+#[derive(Deserialize)]
+struct SnapshotRecord {
+    save_iter: usize,
+    save_path: String,
+    save_file_name: String,
+    settings: SimulationSettings,
+    map: Map,
+    genomes: Vec<ControllerRecord>,
+    cells: Vec<CellRecord>,
+}
+
+#[derive(Serialize)]
+struct CellRef<'a> {
+    kind: KindRef<'a>,
+    organism: Uuid,
+    genome_id: Uuid,
+    life_time: i16,
+    pos: &'a Coord,
+    out_dir: &'a Direction,
+    energy: f32,
+}
+
+#[derive(Serialize)]
+enum KindRef<'a> {
+    Producer(&'a Producer),
+    Conductor,
+    /// `genome` indexes `SnapshotRef::genomes`.
+    Storage { genome: u32, memory: &'a Array1<f32> },
+}
+
+/// Owned counterpart of `CellRef`, field for field.
+#[derive(Deserialize)]
+struct CellRecord {
+    kind: KindRecord,
+    organism: Uuid,
+    genome_id: Uuid,
+    life_time: i16,
+    pos: Coord,
+    out_dir: Direction,
+    energy: f32,
+}
+
+#[derive(Deserialize)]
+enum KindRecord {
+    Producer(Producer),
+    Conductor,
+    Storage { genome: u32, memory: Array1<f32> },
+}
+
+/// A loaded snapshot, the genomes handed back to their buds; buds that shared a genome
+/// when saved share it again.
+pub struct Snapshot {
+    pub save_iter: usize,
+    pub save_path: String,
+    pub save_file_name: String,
+    pub settings: SimulationSettings,
+    pub map: Map,
+    pub cells: Vec<Cell>,
+}
+
+impl<'a> SnapshotRef<'a> {
+    pub fn new(save_iter: usize, save_path: &'a str, save_file_name: &'a str,
+               settings: &'a SimulationSettings, map: &'a Map,
+               cells: impl Iterator<Item = &'a Cell>) -> Self {
+        let mut genomes = Vec::new();
+        // genomes are told apart by their allocation: unmutated daughters hold the parent's
+        let mut index: HashMap<*const (), u32> = HashMap::new();
+        let cells = cells.map(|cell| CellRef {
+            kind: match &cell.kind {
+                CellKind::Producer(p) => KindRef::Producer(p),
+                CellKind::Conductor => KindRef::Conductor,
+                CellKind::Storage(st) => {
+                    let genome = *index.entry(Arc::as_ptr(&st.genome) as *const ()).or_insert_with(|| {
+                        genomes.push(st.genome.record());
+                        (genomes.len() - 1) as u32
+                    });
+                    KindRef::Storage { genome, memory: &st.memory }
+                },
+            },
+            organism: cell.organism,
+            genome_id: cell.genome_id,
+            life_time: cell.life_time,
+            pos: &cell.pos,
+            out_dir: &cell.out_dir,
+            energy: cell.energy,
+        }).collect();
+        Self { save_iter, save_path, save_file_name, settings, map, genomes, cells }
+    }
+}
+
+impl SnapshotRef<'_> {
+    /// Write to `path` through a temp file and a rename, so a crash never leaves a torn snapshot.
+    pub fn write(&self, path: &Path) -> Result<(), Box<dyn Error>> {
+        let tmp_path = path.with_extension("tmp");
+        {
+            let f = File::create(&tmp_path)?;
+            let mut w = BufWriter::new(f);
+            w.write_all(MAGIC)?;
+            w.write_all(&SNAPSHOT_VERSION.to_le_bytes())?;
+            bincode::serde::encode_into_std_write(self, &mut w, bincode::config::standard())?;
+            w.flush()?;
+            w.get_ref().sync_all()?;
+        }
+        fs::rename(&tmp_path, path)?;
+        Ok(())
+    }
+}
+
+impl Snapshot {
+    pub fn read(path: &Path) -> Result<Self, Box<dyn Error>> {
+        let f = File::open(path)?;
+        let mut r = BufReader::new(f);
+
+        let mut magic = [0u8; 8];
+        r.read_exact(&mut magic)?;
+        if &magic != MAGIC {
+            return Err(format!("{:?} is not a simulation snapshot", path).into());
+        }
+        let mut version = [0u8; 4];
+        r.read_exact(&mut version)?;
+        let version = u32::from_le_bytes(version);
+        if version != SNAPSHOT_VERSION {
+            return Err(format!("unsupported snapshot version {} (expected {})",
+                               version, SNAPSHOT_VERSION).into());
+        }
+
+        let record: SnapshotRecord = bincode::serde::decode_from_std_read(&mut r, bincode::config::standard())?;
+        let snapshot = Self::resolve(record).map_err(|e| format!("{:?} is not a valid snapshot: {}", path, e))?;
+        Ok(snapshot)
+    }
+
+    fn resolve(record: SnapshotRecord) -> Result<Self, Box<dyn Error>> {
+        let genomes: Vec<Arc<dyn Controller>> = record.genomes.into_iter().map(|g| Arc::from(g.into_controller())).collect();
+        let cells = record.cells.into_iter().map(|cell| {
+            let kind = match cell.kind {
+                KindRecord::Producer(p) => CellKind::Producer(p),
+                KindRecord::Conductor => CellKind::Conductor,
+                KindRecord::Storage { genome, memory } => {
+                    let genome = genomes.get(genome as usize)
+                        .ok_or_else(|| format!("bud at ({}, {}) refers to missing genome {}", cell.pos.x, cell.pos.y, genome))?;
+                    CellKind::Storage(Storage { genome: genome.clone(), memory })
+                },
+            };
+            Ok(Cell {
+                kind,
+                organism: cell.organism,
+                genome_id: cell.genome_id,
+                life_time: cell.life_time,
+                pos: cell.pos,
+                out_dir: cell.out_dir,
+                energy: cell.energy,
+            })
+        }).collect::<Result<_, Box<dyn Error>>>()?;
+        Ok(Self {
+            save_iter: record.save_iter,
+            save_path: record.save_path,
+            save_file_name: record.save_file_name,
+            settings: record.settings,
+            map: record.map,
+            cells,
+        })
+    }
+
+    /// Check what the simulation takes for granted, so a bad save is an error and not a
+    /// panic later; legacy saves are read into a `Snapshot` to go through it too.
+    pub fn validate(&self) -> Result<(), Box<dyn Error>> {
+        self.settings.validate()?;
+        let map = &self.map;
+        let shape = [map.height, map.width];
+        for (name, layer) in [("organics", &map.organics), ("electric", &map.electric), ("light", &map.light)] {
+            if layer.shape() != shape {
+                return Err(format!("{} layer is {:?}, the map is {}x{}", name, layer.shape(), map.width, map.height).into());
+            }
+        }
+        let n_inputs = self.settings.sensors.input_size();
+        let mut taken = HashSet::new();
+        for cell in &self.cells {
+            let (x, y) = (cell.pos.x, cell.pos.y);
+            if x < 0 || y < 0 || x as usize >= map.width || y as usize >= map.height {
+                return Err(format!("cell at ({}, {}) is out of the map", x, y).into());
+            }
+            if !taken.insert((x, y)) {
+                return Err(format!("two cells at ({}, {})", x, y).into());
+            }
+            if let CellKind::Storage(st) = &cell.kind {
+                st.genome.validate().map_err(|e| format!("genome of the bud at ({}, {}): {}", x, y, e))?;
+                if st.genome.n_inputs() != n_inputs {
+                    return Err(format!("genome of the bud at ({}, {}) takes {} inputs but the sensors give {}",
+                                       x, y, st.genome.n_inputs(), n_inputs).into());
+                }
+                if st.genome.n_outputs() != 4 * 4 {
+                    return Err(format!("genome of the bud at ({}, {}) gives {} outputs, not 16",
+                                       x, y, st.genome.n_outputs()).into());
+                }
+                if st.memory.len() != st.genome.state_size() {
+                    return Err(format!("bud at ({}, {}) remembers {} values, its genome {}",
+                                       x, y, st.memory.len(), st.genome.state_size()).into());
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::common::{new_id, stream_rng, ResourceType};
+    use crate::controller::{random_controller, Mlp};
+    use crate::simulation::Simulation;
+    use ndarray::Array2;
+    use std::path::PathBuf;
+
+    /// Fresh directory in the temp directory, removed with its `_back` twin when dropped.
+    struct TempDir(PathBuf);
+
+    impl TempDir {
+        fn new(name: &str) -> Self {
+            let path = std::env::temp_dir().join(format!("plants_war_{}_{}", name, std::process::id()));
+            let dir = Self(path);
+            dir.remove();
+            dir
+        }
+
+        fn back(&self) -> PathBuf {
+            PathBuf::from(format!("{}_back", self.0.display()))
+        }
+
+        fn remove(&self) {
+            let _ = fs::remove_dir_all(&self.0);
+            let _ = fs::remove_dir_all(self.back());
+        }
+    }
+
+    impl Drop for TempDir {
+        fn drop(&mut self) {
+            self.remove();
+        }
+    }
+
+    fn cell(kind: CellKind, x: i64, y: i64) -> Cell {
+        Cell {
+            kind,
+            organism: Uuid::nil(),
+            genome_id: Uuid::nil(),
+            life_time: 10,
+            pos: Coord { x, y },
+            out_dir: Direction::East,
+            energy: 1.5,
+        }
+    }
+
+    fn bud(genome: &Arc<dyn Controller>, x: i64, y: i64) -> Cell {
+        let storage = Storage { genome: genome.clone(), memory: Array1::zeros(genome.state_size()) };
+        cell(CellKind::Storage(storage), x, y)
+    }
+
+    /// Two buds sharing a genome, one with its own and a leaf, on an 8x6 map.
+    fn snapshot() -> Snapshot {
+        let settings = SimulationSettings::default();
+        let rng = &mut stream_rng(1, 0, 0);
+        let mut genome = || -> Arc<dyn Controller> {
+            Arc::from(random_controller(rng, &settings.genome, settings.sensors.input_size(), 4 * 4))
+        };
+        let (shared, own) = (genome(), genome());
+        let mut cells = vec![bud(&shared, 1, 1), bud(&shared, 5, 2), bud(&own, 7, 5)];
+        cells.push(cell(CellKind::Producer(Producer { resource: ResourceType::Solar }), 2, 1));
+        cells[0].organism = new_id(&mut stream_rng(1, 0, 1));
+        Snapshot {
+            save_iter: 7,
+            save_path: String::new(),
+            save_file_name: "snap".to_string(),
+            settings,
+            map: Map::new(8, 6),
+            cells,
+        }
+    }
+
+    fn genome(cell: &Cell) -> &Arc<dyn Controller> {
+        match &cell.kind {
+            CellKind::Storage(st) => &st.genome,
+            _ => panic!("not a bud"),
+        }
+    }
+
+    #[test]
+    fn round_trip_keeps_shared_genomes() {
+        let dir = TempDir::new("snapshot_round_trip");
+        let original = snapshot();
+        let mut sim = Simulation::new(Some(original.map), dir.0.display().to_string(), "snap".to_string(),
+                                      original.settings);
+        sim.save_iter = original.save_iter;
+        sim.add_cells(original.cells);
+        sim.save_state(true).unwrap();
+
+        let loaded = Simulation::load(&dir.back()).unwrap();
+        assert_eq!(loaded.save_iter, 7);
+        assert_eq!(loaded.cells_count(), 4);
+        let at = |x, y| loaded.cell(&Coord { x, y }).unwrap();
+        assert!(Arc::ptr_eq(genome(at(1, 1)), genome(at(5, 2))));
+        assert!(!Arc::ptr_eq(genome(at(1, 1)), genome(at(7, 5))));
+        assert_eq!(at(1, 1).organism, sim.cell(&Coord { x: 1, y: 1 }).unwrap().organism);
+        assert_eq!(at(2, 1).energy, 1.5);
+        assert!(matches!(at(2, 1).kind, CellKind::Producer(Producer { resource: ResourceType::Solar })));
+
+        // the genome table holds the shared genome once
+        let bytes = fs::read(dir.back().join(SNAPSHOT_FILE)).unwrap();
+        let record: SnapshotRecord = bincode::serde::decode_from_slice(&bytes[12..], bincode::config::standard())
+            .unwrap().0;
+        assert_eq!(record.genomes.len(), 2);
+    }
+
+    #[test]
+    fn valid_snapshot_passes() {
+        snapshot().validate().unwrap();
+    }
+
+    #[test]
+    fn two_cells_at_one_place_are_rejected() {
+        let mut s = snapshot();
+        s.cells[3].pos = Coord { x: 5, y: 2 };
+        assert!(s.validate().unwrap_err().to_string().contains("two cells"));
+    }
+
+    #[test]
+    fn cell_out_of_the_map_is_rejected() {
+        let mut s = snapshot();
+        s.cells[3].pos = Coord { x: 8, y: 0 };
+        assert!(s.validate().is_err());
+    }
+
+    #[test]
+    fn layer_of_another_shape_is_rejected() {
+        let mut s = snapshot();
+        s.map.light = Array2::ones((8, 6));
+        assert!(s.validate().is_err());
+    }
+
+    #[test]
+    fn broken_genome_is_rejected() {
+        let mut s = snapshot();
+        let settings = &s.settings;
+        let rng = &mut stream_rng(2, 0, 0);
+        // right input and output sizes, but the layers do not fit together
+        let mut mlp = Mlp::random(rng, &[settings.sensors.input_size(), 8, 4 * 4], settings.genome.activation,
+                                  settings.genome.output_activation, 0.0, 1.0);
+        mlp.layers[1].w = Array2::zeros((4 * 4, 9));
+        let broken: Arc<dyn Controller> = Arc::new(mlp);
+        s.cells[2] = bud(&broken, 7, 5);
+        assert!(s.validate().unwrap_err().to_string().contains("layer 1"));
+    }
+
+    #[test]
+    fn genome_with_other_outputs_is_rejected() {
+        let mut s = snapshot();
+        let settings = &s.settings;
+        let wrong: Arc<dyn Controller> = Arc::from(random_controller(&mut stream_rng(3, 0, 0), &settings.genome,
+                                                                     settings.sensors.input_size(), 5));
+        s.cells[2] = bud(&wrong, 7, 5);
+        assert!(s.validate().unwrap_err().to_string().contains("outputs"));
+    }
+}
@@ -0,0 +1,102 @@
+use std::collections::HashSet;
+use std::error::Error;
+use std::path::Path;
+
+use crate::cells::{Cell, CellKind};
+use crate::common::{append_csv, ResourceType};
+use crate::events::{DeathCause, Event};
+use crate::map::Map;
+
+/// File the per-step metrics are appended to, next to the state snapshot.
+pub const STATS_FILE: &str = "stats.csv";
+
+/// Metrics of the world after a step.
+#[derive(Clone, Debug, Default)]
+pub struct StepStats {
+    pub step: usize,
+    /// Producers by `ResourceType`: solar, organic, electric.
+    pub producers: [usize; 3],
+    pub conductors: usize,
+    pub buds: usize,
+    /// Cells grown during the step, buds included.
+    pub births: usize,
+    /// Deaths during the step, in the order of `DeathCause::ALL`.
+    pub deaths: [usize; 5],
+    pub total_energy: f64,
+    pub organics_mean: f32,
+    pub organics_max: f32,
+    pub electric_mean: f32,
+    pub electric_max: f32,
+    /// Distinct genome ids among the living cells.
+    pub genomes: usize,
+}
+
+impl StepStats {
+    pub fn collect<'a>(step: usize, cells: impl Iterator<Item = &'a Cell>, map: &Map, events: &[Event]) -> Self {
+        let mut stats = StepStats { step, ..Default::default() };
+        let mut genomes = HashSet::new();
+        for cell in cells {
+            match &cell.kind {
+                CellKind::Producer(p) => stats.producers[match p.resource {
+                    ResourceType::Solar => 0,
+                    ResourceType::Organic => 1,
+                    ResourceType::Electricity => 2,
+                }] += 1,
+                CellKind::Conductor => stats.conductors += 1,
+                CellKind::Storage(_) => stats.buds += 1,
+            }
+            stats.total_energy += cell.energy as f64;
+            genomes.insert(cell.genome_id);
+        }
+        stats.genomes = genomes.len();
+        for event in events {
+            match event {
+                Event::Born { .. } => stats.births += 1,
+                Event::Died { cause, .. } => {
+                    let i = DeathCause::ALL.iter().position(|c| c == cause).expect("every cause is listed");
+                    stats.deaths[i] += 1;
+                },
+                _ => {},
+            }
+        }
+        let max = |a: f32, b: &f32| a.max(*b);
+        stats.organics_mean = map.organics.mean().unwrap_or(0.0);
+        stats.organics_max = map.organics.fold(0.0, max);
+        stats.electric_mean = map.electric.mean().unwrap_or(0.0);
+        stats.electric_max = map.electric.fold(0.0, max);
+        stats
+    }
+
+    pub fn cells(&self) -> usize {
+        self.producers.iter().sum::<usize>() + self.conductors + self.buds
+    }
+}
+
+/// Metrics of the steps since the last flush.
+#[derive(Default)]
+pub struct StatsLog {
+    records: Vec<StepStats>,
+}
+
+impl StatsLog {
+    pub fn record(&mut self, stats: StepStats) {
+        self.records.push(stats);
+    }
+
+    /// Append the pending rows to the CSV at `path` and forget them.
+    pub fn flush(&mut self, path: &Path) -> Result<(), Box<dyn Error>> {
+        let deaths: Vec<String> = DeathCause::ALL.iter().map(|c| format!("deaths_{}", c.name())).collect();
+        let header = format!("step,cells,producers_solar,producers_organic,producers_electric,conductors,buds,births,{},\
+                              total_energy,organics_mean,organics_max,electric_mean,electric_max,genomes", deaths.join(","));
+        let rows = self.records.iter().map(|r| {
+            let deaths: Vec<String> = r.deaths.iter().map(|d| d.to_string()).collect();
+            format!("{},{},{},{},{},{},{},{},{},{},{},{},{},{},{}",
+                    r.step, r.cells(), r.producers[0], r.producers[1], r.producers[2], r.conductors, r.buds,
+                    r.births, deaths.join(","), r.total_energy,
+                    r.organics_mean, r.organics_max, r.electric_mean, r.electric_max, r.genomes)
+        });
+        append_csv(path, &header, rows)?;
+        self.records.clear();
+        Ok(())
+    }
+}
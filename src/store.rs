@@ -0,0 +1,113 @@
+use ndarray::Array2;
+use std::ops::Index;
+
+use crate::cells::Cell;
+use crate::common::Coord;
+
+/// Slot of a cell in the store; valid until the cell is removed.
+pub type CellId = u32;
+
+/// Cells of the map: a grid of slot ids over a slab of cells. A lookup by coordinate is
+/// a single grid read, and iteration goes row by row.
+pub struct CellStore {
+    grid: Array2<Option<CellId>>,
+    slab: Vec<Option<Cell>>,
+    /// Emptied slots, reused last in first out.
+    free: Vec<CellId>,
+    len: usize,
+}
+
+impl CellStore {
+    pub fn new(width: usize, height: usize) -> Self {
+        Self { grid: Array2::from_elem((height, width), None), slab: Vec::new(), free: Vec::new(), len: 0 }
+    }
+
+    fn index_of(&self, coord: &Coord) -> Option<(usize, usize)> {
+        let (h, w) = self.grid.dim();
+        (coord.x >= 0 && coord.y >= 0 && (coord.x as usize) < w && (coord.y as usize) < h)
+            .then_some((coord.y as usize, coord.x as usize))
+    }
+
+    fn slot(&self, coord: &Coord) -> Option<CellId> {
+        self.grid[self.index_of(coord)?]
+    }
+
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    pub fn contains(&self, coord: &Coord) -> bool {
+        self.slot(coord).is_some()
+    }
+
+    pub fn get(&self, coord: &Coord) -> Option<&Cell> {
+        self.slab[self.slot(coord)? as usize].as_ref()
+    }
+
+    pub fn get_mut(&mut self, coord: &Coord) -> Option<&mut Cell> {
+        let id = self.slot(coord)?;
+        self.slab[id as usize].as_mut()
+    }
+
+    /// Put `cell` at `cell.pos`, returning the cell it replaces.
+    pub fn insert(&mut self, cell: Cell) -> Option<Cell> {
+        let Some(at) = self.index_of(&cell.pos) else {
+            panic!("Cell at {:?} is out of the map!", cell.pos);
+        };
+        let old = self.remove(&cell.pos);
+        let id = match self.free.pop() {
+            Some(id) => {
+                self.slab[id as usize] = Some(cell);
+                id
+            },
+            None => {
+                self.slab.push(Some(cell));
+                (self.slab.len() - 1) as CellId
+            },
+        };
+        self.grid[at] = Some(id);
+        self.len += 1;
+        old
+    }
+
+    pub fn remove(&mut self, coord: &Coord) -> Option<Cell> {
+        let at = self.index_of(coord)?;
+        let id = self.grid[at].take()?;
+        self.free.push(id);
+        self.len -= 1;
+        self.slab[id as usize].take()
+    }
+
+    /// Coordinates of all cells, row by row.
+    pub fn coords(&self) -> Vec<Coord> {
+        let mut coords = Vec::with_capacity(self.len);
+        for ((y, x), id) in self.grid.indexed_iter() {
+            if id.is_some() {
+                coords.push(Coord { x: x as i64, y: y as i64 });
+            }
+        }
+        coords
+    }
+
+    /// Cells row by row.
+    pub fn iter(&self) -> impl Iterator<Item = &Cell> {
+        self.grid.iter().filter_map(|id| self.slab[(*id)? as usize].as_ref())
+    }
+
+    /// Cells in slot order, for updates that do not depend on the order.
+    pub fn iter_mut(&mut self) -> impl Iterator<Item = &mut Cell> {
+        self.slab.iter_mut().flatten()
+    }
+}
+
+impl Index<&Coord> for CellStore {
+    type Output = Cell;
+
+    fn index(&self, coord: &Coord) -> &Cell {
+        self.get(coord).unwrap_or_else(|| panic!("There is no cell at {coord:?}!"))
+    }
+}
@@ -0,0 +1,390 @@
+use std::error::Error;
+use std::fs::{self, File, OpenOptions};
+use std::io::{BufRead, BufReader, BufWriter, Read, Seek, SeekFrom, Write};
+use std::path::Path;
+
+use crate::render::ViewCell;
+
+/// Extension of the trajectory file written next to the views.
+pub const TRAJECTORY_EXT: &str = "traj";
+const MAGIC: &[u8; 6] = b"PWTRAJ";
+const VERSION: u32 = 1;
+/// Names of the cell codes, by `CellKind::index`; code 0 is an empty place.
+pub const KINDS: [&str; 3] = ["producer", "conductor", "bud"];
+
+/// Header: magic, version, width and height, then the kind dictionary (count, then
+/// length-prefixed names). Frames follow, appended one per step: the step and the
+/// payload length, then the grid row by row as runs of (LEB128 length, code).
+pub struct TrajectoryWriter {
+    out: BufWriter<File>,
+}
+
+impl TrajectoryWriter {
+    /// Open `path` to append frames, writing the header if the file is new or `overwrite`.
+    pub fn open(path: &Path, width: usize, height: usize, overwrite: bool) -> Result<Self, Box<dyn Error>> {
+        if !overwrite && path.exists() {
+            let header = Trajectory::read_header(&mut BufReader::new(File::open(path)?))?;
+            if (header.width, header.height) != (width, height) {
+                return Err(format!("{:?} holds a {}x{} world, not {}x{}",
+                                   path, header.width, header.height, width, height).into());
+            }
+            // a frame torn by a crash is cut off, so the new ones follow the last whole frame
+            let mut file = BufReader::new(OpenOptions::new().read(true).write(true).open(path)?);
+            Trajectory::read_header(&mut file)?;
+            let (_, end) = Trajectory::scan(&mut file)?;
+            let mut f = file.into_inner();
+            f.set_len(end)?;
+            f.seek(SeekFrom::Start(end))?;
+            return Ok(Self { out: BufWriter::new(f) });
+        }
+        let mut out = BufWriter::new(File::create(path)?);
+        out.write_all(MAGIC)?;
+        out.write_all(&VERSION.to_le_bytes())?;
+        out.write_all(&(width as u32).to_le_bytes())?;
+        out.write_all(&(height as u32).to_le_bytes())?;
+        out.write_all(&[KINDS.len() as u8])?;
+        for kind in KINDS {
+            out.write_all(&[kind.len() as u8])?;
+            out.write_all(kind.as_bytes())?;
+        }
+        Ok(Self { out })
+    }
+
+    /// Append the frame of `step`; `grid` holds a code per place, row by row.
+    pub fn write(&mut self, step: usize, grid: &[u8]) -> Result<(), Box<dyn Error>> {
+        let mut payload = Vec::new();
+        for run in grid.chunk_by(|a, b| a == b) {
+            write_varint(&mut payload, run.len() as u64);
+            payload.push(run[0]);
+        }
+        self.out.write_all(&(step as u64).to_le_bytes())?;
+        self.out.write_all(&(payload.len() as u32).to_le_bytes())?;
+        self.out.write_all(&payload)?;
+        Ok(())
+    }
+
+    /// Push the buffered frames to the file; dropping the writer does it as well.
+    pub fn flush(&mut self) -> Result<(), Box<dyn Error>> {
+        self.out.flush()?;
+        Ok(())
+    }
+}
+
+/// Step, payload offset and length of a frame.
+type FrameEntry = (usize, u64, usize);
+
+struct Header {
+    width: usize,
+    height: usize,
+    kinds: Vec<String>,
+}
+
+/// Reader of a trajectory file. Opening indexes the frames, so any step can be read
+/// directly; if a step was written twice (a run resumed from an older save) the last
+/// frame wins.
+pub struct Trajectory {
+    file: BufReader<File>,
+    pub width: usize,
+    pub height: usize,
+    /// Names of the codes from 1 on.
+    kinds: Vec<String>,
+    /// Every frame, by step.
+    index: Vec<FrameEntry>,
+}
+
+impl Trajectory {
+    fn read_header(r: &mut impl Read) -> Result<Header, Box<dyn Error>> {
+        let mut magic = [0u8; 6];
+        r.read_exact(&mut magic)?;
+        if &magic != MAGIC {
+            return Err("not a trajectory file".into());
+        }
+        let version = read_u32(r)?;
+        if version != VERSION {
+            return Err(format!("trajectory version {} is not supported (expected {})", version, VERSION).into());
+        }
+        let (width, height) = (read_u32(r)? as usize, read_u32(r)? as usize);
+        let mut n = [0u8; 1];
+        r.read_exact(&mut n)?;
+        let mut kinds = Vec::with_capacity(n[0] as usize);
+        for _ in 0..n[0] {
+            let mut len = [0u8; 1];
+            r.read_exact(&mut len)?;
+            let mut name = vec![0u8; len[0] as usize];
+            r.read_exact(&mut name)?;
+            kinds.push(String::from_utf8(name)?);
+        }
+        Ok(Header { width, height, kinds })
+    }
+
+    /// Every whole frame from the position of `file` on, in file order, and the end of the last of them; a frame cut short by a crash
+    /// and anything after it are left out.
+    fn scan(file: &mut BufReader<File>) -> Result<(Vec<FrameEntry>, u64), Box<dyn Error>> {
+        let end = file.get_ref().metadata()?.len();
+        let mut index: Vec<FrameEntry> = Vec::new();
+        let mut at = file.stream_position()?;
+        while at + 12 <= end {
+            let mut step = [0u8; 8];
+            file.read_exact(&mut step)?;
+            let len = read_u32(file)? as usize;
+            let offset = at + 12;
+            if offset + len as u64 > end { break; }
+            index.push((u64::from_le_bytes(step) as usize, offset, len));
+            file.seek_relative(len as i64)?;
+            at = offset + len as u64;
+        }
+        Ok((index, at))
+    }
+
+    pub fn open(path: &Path) -> Result<Self, Box<dyn Error>> {
+        let mut file = BufReader::new(File::open(path)?);
+        let header = Self::read_header(&mut file)?;
+        let (mut index, _) = Self::scan(&mut file)?;
+        // stable, so the last of the equal steps stays last
+        index.sort_by_key(|f| f.0);
+        index.reverse();
+        index.dedup_by_key(|f| f.0);
+        index.reverse();
+        Ok(Self { file, width: header.width, height: header.height, kinds: header.kinds, index })
+    }
+
+    /// Steps with a frame, in order.
+    pub fn steps(&self) -> Vec<usize> {
+        self.index.iter().map(|f| f.0).collect()
+    }
+
+    /// Codes of every place at `step`, row by row.
+    pub fn grid(&mut self, step: usize) -> Result<Vec<u8>, Box<dyn Error>> {
+        let Ok(i) = self.index.binary_search_by_key(&step, |f| f.0) else {
+            return Err(format!("there is no frame of step {}", step).into());
+        };
+        let (_, offset, len) = self.index[i];
+        self.file.seek(SeekFrom::Start(offset))?;
+        let mut payload = vec![0u8; len];
+        self.file.read_exact(&mut payload)?;
+
+        let mut grid = Vec::with_capacity(self.width * self.height);
+        let mut bytes = payload.iter();
+        while let Some(run) = read_varint(&mut bytes) {
+            let code = *bytes.next().ok_or("a run without a code")?;
+            if run > (self.width * self.height - grid.len()) as u64 {
+                return Err(format!("frame of step {} runs past {} places", step, self.width * self.height).into());
+            }
+            grid.resize(grid.len() + run as usize, code);
+        }
+        if grid.len() != self.width * self.height {
+            return Err(format!("frame of step {} has {} places, not {}", step, grid.len(), self.width * self.height).into());
+        }
+        Ok(grid)
+    }
+
+    /// Cells at `step`, row by row, as `save_view` used to write them.
+    pub fn cells(&mut self, step: usize) -> Result<Vec<ViewCell>, Box<dyn Error>> {
+        let width = self.width;
+        let grid = self.grid(step)?;
+        let mut cells = Vec::new();
+        for (i, &code) in grid.iter().enumerate() {
+            if code == 0 { continue; }
+            let kind = self.kinds.get(code as usize - 1).ok_or_else(|| format!("unknown cell code {}", code))?;
+            cells.push(((i % width) as i64, (i / width) as i64, kind.clone()));
+        }
+        Ok(cells)
+    }
+}
+
+fn read_u32(r: &mut impl Read) -> std::io::Result<u32> {
+    let mut b = [0u8; 4];
+    r.read_exact(&mut b)?;
+    Ok(u32::from_le_bytes(b))
+}
+
+fn write_varint(out: &mut Vec<u8>, mut v: u64) {
+    while v >= 0x80 {
+        out.push(v as u8 | 0x80);
+        v >>= 7;
+    }
+    out.push(v as u8);
+}
+
+fn read_varint<'a>(bytes: &mut impl Iterator<Item = &'a u8>) -> Option<u64> {
+    let (mut v, mut shift) = (0u64, 0);
+    loop {
+        // longer than any u64, the data is corrupt
+        if shift >= 64 {
+            return None;
+        }
+        let b = *bytes.next()?;
+        v |= ((b & 0x7f) as u64) << shift;
+        if b & 0x80 == 0 {
+            return Some(v);
+        }
+        shift += 7;
+    }
+}
+
+/// Views in the old format: a `<name>_meta.txt` with the map size and a
+/// `<name>_<step>.csv` of `x,y,kind` lines per step.
+pub struct CsvViews {
+    pub width: usize,
+    pub height: usize,
+    /// Steps found, in order.
+    pub steps: Vec<usize>,
+}
+
+impl CsvViews {
+    pub fn list(dir: &Path, name: &str) -> Result<Self, Box<dyn Error>> {
+        let meta = fs::read_to_string(dir.join(format!("{}_meta.txt", name)))?;
+        // "height,width"
+        let mut size = meta.trim().split(',').map(|v| v.trim().parse::<usize>());
+        let (Some(height), Some(width)) = (size.next(), size.next()) else {
+            return Err(format!("bad view meta in {:?}", dir).into());
+        };
+        let prefix = format!("{}_", name);
+        let mut steps: Vec<usize> = fs::read_dir(dir)?
+            .filter_map(|e| e.ok()?.file_name().into_string().ok())
+            .filter_map(|f| f.strip_prefix(&prefix)?.strip_suffix(".csv")?.parse().ok())
+            .collect();
+        steps.sort_unstable();
+        Ok(Self { width: width?, height: height?, steps })
+    }
+
+    pub fn read(dir: &Path, name: &str, step: usize) -> Result<Vec<ViewCell>, Box<dyn Error>> {
+        let f = File::open(dir.join(format!("{}_{}.csv", name, step)))?;
+        let mut cells = Vec::new();
+        for line in BufReader::new(f).lines() {
+            let line = line?;
+            let mut parts = line.split(',');
+            let (Some(x), Some(y), Some(kind)) = (parts.next(), parts.next(), parts.next()) else {
+                return Err(format!("bad view line {:?}", line).into());
+            };
+            cells.push((x.parse()?, y.parse()?, kind.to_string()));
+        }
+        Ok(cells)
+    }
+}
+
+/// Pack the CSV views of `dir` into `<dir>/<name>.traj`; returns the number of frames.
+pub fn convert_csv(dir: &Path, name: &str) -> Result<usize, Box<dyn Error>> {
+    let views = CsvViews::list(dir, name)?;
+    let path = dir.join(format!("{}.{}", name, TRAJECTORY_EXT));
+    let mut out = TrajectoryWriter::open(&path, views.width, views.height, true)?;
+    let mut grid = vec![0u8; views.width * views.height];
+    for &step in &views.steps {
+        grid.fill(0);
+        for (x, y, kind) in CsvViews::read(dir, name, step)? {
+            let code = KINDS.iter().position(|k| *k == kind).ok_or_else(|| format!("unknown cell kind {:?}", kind))?;
+            if x < 0 || y < 0 || x as usize >= views.width || y as usize >= views.height {
+                return Err(format!("cell at ({}, {}) of step {} is out of the map", x, y, step).into());
+            }
+            grid[y as usize * views.width + x as usize] = code as u8 + 1;
+        }
+        out.write(step, &grid)?;
+    }
+    Ok(views.steps.len())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::path::PathBuf;
+
+    /// Fresh file in the temp directory, removed when dropped.
+    struct TempFile(PathBuf);
+
+    impl TempFile {
+        fn new(name: &str) -> Self {
+            let path = std::env::temp_dir().join(format!("plants_war_{}_{}.{}", name, std::process::id(), TRAJECTORY_EXT));
+            let _ = fs::remove_file(&path);
+            Self(path)
+        }
+    }
+
+    impl Drop for TempFile {
+        fn drop(&mut self) {
+            let _ = fs::remove_file(&self.0);
+        }
+    }
+
+    /// 4x3 grid that differs from step to step.
+    fn grid(step: usize) -> Vec<u8> {
+        (0..12).map(|i| ((i + step) % 7 / 2) as u8).collect()
+    }
+
+    fn write(path: &Path, steps: impl IntoIterator<Item = usize>, overwrite: bool) {
+        let mut out = TrajectoryWriter::open(path, 4, 3, overwrite).unwrap();
+        for step in steps {
+            out.write(step, &grid(step)).unwrap();
+        }
+    }
+
+    #[test]
+    fn round_trip() {
+        let file = TempFile::new("round_trip");
+        write(&file.0, 0..5, true);
+        let mut t = Trajectory::open(&file.0).unwrap();
+        assert_eq!((t.width, t.height), (4, 3));
+        assert_eq!(t.steps(), vec![0, 1, 2, 3, 4]);
+        for step in 0..5 {
+            assert_eq!(t.grid(step).unwrap(), grid(step));
+        }
+        let cells = t.cells(0).unwrap();
+        assert_eq!(cells.len(), grid(0).iter().filter(|&&c| c != 0).count());
+        assert_eq!(cells[0], (2, 0, "producer".to_string()));
+    }
+
+    #[test]
+    fn seeks_to_a_middle_step() {
+        let file = TempFile::new("seek");
+        write(&file.0, 0..10, true);
+        let mut t = Trajectory::open(&file.0).unwrap();
+        assert_eq!(t.grid(6).unwrap(), grid(6));
+        assert_eq!(t.grid(2).unwrap(), grid(2));
+        assert!(t.grid(10).is_err());
+    }
+
+    #[test]
+    fn last_frame_of_a_step_wins() {
+        let file = TempFile::new("repeat");
+        write(&file.0, 0..4, true);
+        // a run resumed from an older save writes step 2 again
+        let mut out = TrajectoryWriter::open(&file.0, 4, 3, false).unwrap();
+        out.write(2, &grid(5)).unwrap();
+        drop(out);
+        let mut t = Trajectory::open(&file.0).unwrap();
+        assert_eq!(t.steps(), vec![0, 1, 2, 3]);
+        assert_eq!(t.grid(2).unwrap(), grid(5));
+        assert_eq!(t.grid(3).unwrap(), grid(3));
+    }
+
+    #[test]
+    fn append_after_a_torn_frame() {
+        let file = TempFile::new("torn");
+        write(&file.0, 0..4, true);
+        let len = fs::metadata(&file.0).unwrap().len();
+        OpenOptions::new().write(true).open(&file.0).unwrap().set_len(len - 3).unwrap();
+        assert_eq!(Trajectory::open(&file.0).unwrap().steps(), vec![0, 1, 2]);
+
+        write(&file.0, 3..6, false);
+        let mut t = Trajectory::open(&file.0).unwrap();
+        assert_eq!(t.steps(), vec![0, 1, 2, 3, 4, 5]);
+        for step in 0..6 {
+            assert_eq!(t.grid(step).unwrap(), grid(step));
+        }
+    }
+
+    #[test]
+    fn overlong_varint_is_rejected() {
+        let bytes = [0xffu8; 11];
+        assert_eq!(read_varint(&mut bytes.iter()), None);
+        let mut payload = Vec::new();
+        write_varint(&mut payload, u64::MAX);
+        assert_eq!(read_varint(&mut payload.iter()), Some(u64::MAX));
+    }
+
+    #[test]
+    fn other_size_is_refused() {
+        let file = TempFile::new("size");
+        write(&file.0, 0..1, true);
+        assert!(TrajectoryWriter::open(&file.0, 5, 3, false).is_err());
+    }
+}
@@ -0,0 +1,244 @@
+use std::io::{self, Stdout, Write};
+use std::time::{Duration, Instant};
+
+use crossterm::event::{self, Event, KeyCode, KeyEventKind, MouseButton, MouseEventKind};
+use crossterm::style::{Color, Print, ResetColor, SetBackgroundColor, SetForegroundColor};
+use crossterm::{cursor, execute, queue, terminal};
+
+use crate::cells::{Cell, CellKind};
+use crate::common::Coord;
+use crate::events::DeathCause;
+use crate::render::{Palette, Rgb};
+use crate::simulation::Simulation;
+use crate::stats::StepStats;
+
+/// Columns of the side panel.
+const PANEL: u16 = 36;
+/// Shortest pause between steps, reached by speeding up.
+const MIN_DELAY: Duration = Duration::from_millis(1);
+const KEYS: &str = "space pause  n step  +/- speed  arrows scroll  z/x zoom  click select  q quit";
+
+/// Terminal view of a running simulation. Every screen cell shows two map squares of
+/// `zoom` x `zoom` cells with a half block; a square takes the colour of a bud in it if
+/// there is one, else of any cell.
+pub struct Viewer {
+    out: Stdout,
+    palette: Palette,
+    /// Map cell in the top left corner.
+    origin: (i64, i64),
+    zoom: usize,
+    paused: bool,
+    step_once: bool,
+    quit: bool,
+    /// Pause between steps while running.
+    delay: Duration,
+    selected: Option<Coord>,
+}
+
+impl Viewer {
+    /// Take over the terminal; it is given back when the viewer is dropped.
+    pub fn start(palette: Palette) -> io::Result<Self> {
+        terminal::enable_raw_mode()?;
+        let mut out = io::stdout();
+        execute!(out, terminal::EnterAlternateScreen, cursor::Hide, event::EnableMouseCapture)?;
+        Ok(Self {
+            out,
+            palette,
+            origin: (0, 0),
+            zoom: 1,
+            paused: false,
+            step_once: false,
+            quit: false,
+            delay: Duration::from_millis(100),
+            selected: None,
+        })
+    }
+
+    /// Show the state after a step and handle the keys until the next step is due;
+    /// `false` once the user quits.
+    pub fn frame(&mut self, simulation: &Simulation) -> io::Result<bool> {
+        let stats = StepStats::collect(simulation.save_iter, simulation.cells(), simulation.world_map(),
+                                       simulation.events());
+        self.draw(simulation, &stats)?;
+        let deadline = Instant::now() + self.delay;
+        loop {
+            let wait = if self.paused {
+                Duration::from_millis(250)
+            } else {
+                deadline.saturating_duration_since(Instant::now())
+            };
+            if event::poll(wait)? {
+                if self.handle(event::read()?, simulation) {
+                    self.draw(simulation, &stats)?;
+                }
+            } else if !self.paused {
+                break;
+            }
+            if self.quit {
+                return Ok(false);
+            }
+            if std::mem::take(&mut self.step_once) || (!self.paused && Instant::now() >= deadline) {
+                break;
+            }
+        }
+        Ok(true)
+    }
+
+    /// Apply an input event; `true` if the picture changed.
+    fn handle(&mut self, event: Event, simulation: &Simulation) -> bool {
+        let (cols, rows) = terminal::size().unwrap_or((80, 24));
+        let (view_w, view_h) = (view_width(cols) as i64, rows.saturating_sub(1) as i64 * 2);
+        let step = (view_w.min(view_h) / 4).max(1) * self.zoom as i64;
+        match event {
+            Event::Key(key) if key.kind != KeyEventKind::Release => match key.code {
+                KeyCode::Char('q') | KeyCode::Esc => self.quit = true,
+                KeyCode::Char(' ') => self.paused = !self.paused,
+                KeyCode::Char('n') | KeyCode::Char('.') => {
+                    self.paused = true;
+                    self.step_once = true;
+                },
+                KeyCode::Char('+') | KeyCode::Char('=') => self.delay = (self.delay / 2).max(MIN_DELAY),
+                KeyCode::Char('-') => self.delay = (self.delay * 2).clamp(MIN_DELAY, Duration::from_secs(2)),
+                KeyCode::Left | KeyCode::Char('h') => self.origin.0 -= step,
+                KeyCode::Right | KeyCode::Char('l') => self.origin.0 += step,
+                KeyCode::Up | KeyCode::Char('k') => self.origin.1 -= step,
+                KeyCode::Down | KeyCode::Char('j') => self.origin.1 += step,
+                KeyCode::Char('z') => self.zoom = (self.zoom / 2).max(1),
+                KeyCode::Char('x') => self.zoom = (self.zoom * 2).min(64),
+                _ => return false,
+            },
+            Event::Mouse(mouse) => match mouse.kind {
+                MouseEventKind::Down(MouseButton::Left) if (mouse.column as i64) < view_w => {
+                    let coord = Coord {
+                        x: self.origin.0 + mouse.column as i64 * self.zoom as i64,
+                        y: self.origin.1 + mouse.row as i64 * 2 * self.zoom as i64,
+                    };
+                    self.selected = simulation.world_map().in_bounds(coord.x, coord.y).then_some(coord);
+                },
+                MouseEventKind::ScrollUp => self.zoom = (self.zoom / 2).max(1),
+                MouseEventKind::ScrollDown => self.zoom = (self.zoom * 2).min(64),
+                _ => return false,
+            },
+            Event::Resize(_, _) => {},
+            _ => return false,
+        }
+        let map = simulation.world_map();
+        self.origin.0 = self.origin.0.clamp(0, (map.width as i64 - 1).max(0));
+        self.origin.1 = self.origin.1.clamp(0, (map.height as i64 - 1).max(0));
+        true
+    }
+
+    /// Colour of the `zoom` x `zoom` square with its top left corner at `(x, y)`.
+    fn square(&self, simulation: &Simulation, x: i64, y: i64) -> Rgb {
+        let map = simulation.world_map();
+        let mut colour = None;
+        for dy in 0..self.zoom as i64 {
+            for dx in 0..self.zoom as i64 {
+                if !map.in_bounds(x + dx, y + dy) { continue; }
+                let Some(cell) = simulation.cell(&Coord { x: x + dx, y: y + dy }) else { continue };
+                if matches!(cell.kind, CellKind::Storage(_)) {
+                    return self.palette.bud;
+                }
+                colour.get_or_insert(self.palette.cell(&cell.kind));
+            }
+        }
+        colour.unwrap_or(if map.in_bounds(x, y) { self.palette.background } else { [0, 0, 0] })
+    }
+
+    fn draw(&mut self, simulation: &Simulation, stats: &StepStats) -> io::Result<()> {
+        let (cols, rows) = terminal::size()?;
+        let (view_w, view_h) = (view_width(cols), rows.saturating_sub(1));
+        let z = self.zoom as i64;
+        for row in 0..view_h {
+            queue!(self.out, cursor::MoveTo(0, row))?;
+            for col in 0..view_w {
+                let x = self.origin.0 + col as i64 * z;
+                let y = self.origin.1 + row as i64 * 2 * z;
+                let (top, bottom) = (self.square(simulation, x, y), self.square(simulation, x, y + z));
+                let mut top = rgb(top);
+                if self.selected.as_ref().is_some_and(|s| (x..x + z).contains(&s.x) && (y..y + z).contains(&s.y)) {
+                    top = Color::White;
+                }
+                queue!(self.out, SetForegroundColor(top), SetBackgroundColor(rgb(bottom)), Print('▀'))?;
+            }
+        }
+        queue!(self.out, ResetColor)?;
+
+        let mut lines = vec![
+            format!("step        {}", stats.step),
+            format!("state       {}", if self.paused { "paused" } else { "running" }),
+            format!("delay       {} ms", self.delay.as_millis()),
+            format!("view        {},{} zoom {}", self.origin.0, self.origin.1, self.zoom),
+            String::new(),
+            format!("cells       {}", stats.cells()),
+            format!("  leaves    {}", stats.producers[0]),
+            format!("  roots     {}", stats.producers[1]),
+            format!("  antennas  {}", stats.producers[2]),
+            format!("  conductors {}", stats.conductors),
+            format!("  buds      {}", stats.buds),
+            format!("births      {}", stats.births),
+        ];
+        for (cause, n) in DeathCause::ALL.iter().zip(stats.deaths) {
+            lines.push(format!("  {:<10}{}", cause.name(), n));
+        }
+        lines.extend([
+            format!("energy      {:.1}", stats.total_energy),
+            format!("organics    {:.3} / {:.2}", stats.organics_mean, stats.organics_max),
+            format!("electric    {:.3} / {:.2}", stats.electric_mean, stats.electric_max),
+            format!("genomes     {}", stats.genomes),
+            String::new(),
+        ]);
+        match &self.selected {
+            Some(coord) => {
+                lines.push(format!("selected    {},{}", coord.x, coord.y));
+                match simulation.cell(coord) {
+                    Some(cell) => lines.extend(describe(cell)),
+                    None => lines.push("  empty".to_string()),
+                }
+            },
+            None => lines.push("click a cell to select".to_string()),
+        }
+        // no room for the panel, the map takes the whole width
+        let width = (cols - view_w).saturating_sub(1) as usize;
+        for row in (0..view_h).filter(|_| width > 0) {
+            let line = lines.get(row as usize).map_or("", |l| l.as_str());
+            let line: String = line.chars().take(width).collect();
+            queue!(self.out, cursor::MoveTo(view_w + 1, row), Print(format!("{:<width$}", line)))?;
+        }
+        let keys: String = KEYS.chars().take(cols as usize).collect();
+        queue!(self.out, cursor::MoveTo(0, view_h), terminal::Clear(terminal::ClearType::CurrentLine), Print(keys))?;
+        self.out.flush()
+    }
+}
+
+impl Drop for Viewer {
+    fn drop(&mut self) {
+        let _ = execute!(self.out, event::DisableMouseCapture, cursor::Show, terminal::LeaveAlternateScreen);
+        let _ = terminal::disable_raw_mode();
+    }
+}
+
+/// Columns of the map view; the panel is left out of a terminal too narrow for both.
+fn view_width(cols: u16) -> u16 {
+    if cols > PANEL { cols - PANEL } else { cols }
+}
+
+fn rgb(c: Rgb) -> Color {
+    Color::Rgb { r: c[0], g: c[1], b: c[2] }
+}
+
+/// Panel lines about a cell.
+fn describe(cell: &Cell) -> Vec<String> {
+    let mut lines = vec![
+        format!("  kind      {}", cell.kind.name()),
+        format!("  energy    {:.3}", cell.energy),
+        format!("  life time {}", cell.life_time),
+        format!("  organism  {}", &cell.organism.to_string()[..8]),
+        format!("  genome id {}", &cell.genome_id.to_string()[..8]),
+    ];
+    if let CellKind::Storage(st) = &cell.kind {
+        lines.push(format!("  genome    {}", st.genome.describe()));
+        lines.push(format!("  memory    {}", st.genome.state_size()));
+    }
+    lines
+}